@@ -1,3 +1,17 @@
+// The build script compiles in a single mechanism to clear memory, so
+// these benchmarks, and in particular the 64 KiB and 1 MiB ones, only
+// measure the one which was selected. To compare them, run the
+// benchmarks once for each, and compare the results:
+//
+//     cargo +nightly bench --bench clear_on_drop
+//     cargo +nightly bench --bench clear_on_drop --features no_asm
+//     cargo +nightly bench --bench clear_on_drop --features "no_asm no_cc"
+//     cargo +nightly bench --bench clear_on_drop --features volatile
+//
+// which select inline assembly (where available), the C function, the
+// atomic fallback and volatile writes, in that order.
+// `hide::active_backend` tells which one a build uses.
+
 #![feature(test)]
 
 extern crate test;
@@ -38,3 +52,15 @@ fn clear_on_drop_large(b: &mut Bencher) {
     let mut place = Data::default();
     b.iter(|| { ClearOnDrop::new(&mut place); })
 }
+
+#[bench]
+fn clear_on_drop_slice_64k(b: &mut Bencher) {
+    let mut place = vec![0u8; 64 * 1024];
+    b.iter(|| { ClearOnDrop::new(&mut place[..]); })
+}
+
+#[bench]
+fn clear_on_drop_slice_1m(b: &mut Bencher) {
+    let mut place = vec![0u8; 1024 * 1024];
+    b.iter(|| { ClearOnDrop::new(&mut place[..]); })
+}
@@ -4,7 +4,10 @@ extern crate test;
 use test::Bencher;
 
 extern crate clear_on_drop;
-use clear_on_drop::{clear_stack_on_return, clear_stack_on_return_fnonce};
+use clear_on_drop::{
+    clear_stack_bytes, clear_stack_bytes_recursive, clear_stack_on_return,
+    clear_stack_on_return_fnonce,
+};
 
 #[bench]
 fn clear_stack_on_return_tiny(b: &mut Bencher) {
@@ -25,3 +28,33 @@ fn clear_stack_on_return_fnonce_tiny(b: &mut Bencher) {
 fn clear_stack_on_return_fnonce_small(b: &mut Bencher) {
     b.iter(|| clear_stack_on_return_fnonce(2, || 0x41))
 }
+
+#[bench]
+fn clear_stack_bytes_4k(b: &mut Bencher) {
+    b.iter(|| clear_stack_bytes(4096))
+}
+
+#[bench]
+fn clear_stack_bytes_recursive_4k(b: &mut Bencher) {
+    b.iter(|| clear_stack_bytes_recursive(4096))
+}
+
+#[bench]
+fn clear_stack_bytes_64k(b: &mut Bencher) {
+    b.iter(|| clear_stack_bytes(65536))
+}
+
+#[bench]
+fn clear_stack_bytes_recursive_64k(b: &mut Bencher) {
+    b.iter(|| clear_stack_bytes_recursive(65536))
+}
+
+#[bench]
+fn clear_stack_bytes_256k(b: &mut Bencher) {
+    b.iter(|| clear_stack_bytes(262144))
+}
+
+#[bench]
+fn clear_stack_bytes_recursive_256k(b: &mut Bencher) {
+    b.iter(|| clear_stack_bytes_recursive(262144))
+}
@@ -0,0 +1,60 @@
+#![feature(test)]
+
+extern crate test;
+use test::Bencher;
+
+extern crate clear_on_drop;
+use clear_on_drop::ClearingAllocator;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+
+static CLEARING: ClearingAllocator<System> = ClearingAllocator::new(System);
+
+fn alloc_dealloc<A: GlobalAlloc>(b: &mut Bencher, allocator: &A, size: usize) {
+    let layout = Layout::from_size_align(size, 8).unwrap();
+    b.iter(|| unsafe {
+        let ptr = allocator.alloc(layout);
+        test::black_box(ptr);
+        allocator.dealloc(ptr, layout);
+    })
+}
+
+fn realloc<A: GlobalAlloc>(b: &mut Bencher, allocator: &A, size: usize) {
+    let layout = Layout::from_size_align(size, 8).unwrap();
+    b.iter(|| unsafe {
+        let ptr = allocator.alloc(layout);
+        let ptr = allocator.realloc(ptr, layout, size * 2);
+        test::black_box(ptr);
+        allocator.dealloc(ptr, Layout::from_size_align(size * 2, 8).unwrap());
+    })
+}
+
+#[bench]
+fn system_small(b: &mut Bencher) {
+    alloc_dealloc(b, &System, 32)
+}
+
+#[bench]
+fn clearing_small(b: &mut Bencher) {
+    alloc_dealloc(b, &CLEARING, 32)
+}
+
+#[bench]
+fn system_large(b: &mut Bencher) {
+    alloc_dealloc(b, &System, 64 * 1024)
+}
+
+#[bench]
+fn clearing_large(b: &mut Bencher) {
+    alloc_dealloc(b, &CLEARING, 64 * 1024)
+}
+
+#[bench]
+fn system_realloc(b: &mut Bencher) {
+    realloc(b, &System, 1024)
+}
+
+#[bench]
+fn clearing_realloc(b: &mut Bencher) {
+    realloc(b, &CLEARING, 1024)
+}
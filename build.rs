@@ -1,9 +1,97 @@
 extern crate cc;
 
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+// Selects the strongest mechanism available to `hide_mem`, and sets the
+// `clear_on_drop_hide` cfg to its name. The `no_asm` and `no_cc`
+// features skip the corresponding mechanisms, and the `volatile`
+// feature selects volatile writes instead. When inline assembly is
+// available, also sets `clear_on_drop_asm`, which enables clearing the
+// registers, and, unless `volatile` is selected, clearing the stack in a
+// single frame.
 fn main() {
-    if !cfg!(feature = "no_cc") {
-        cc::Build::new()
-            .file("src/hide.c")
-            .compile("clear_on_drop");
+    println!(concat!(
+        "cargo:rustc-check-cfg=cfg(clear_on_drop_hide, ",
+        "values(\"asm\", \"cc\", \"atomic\", \"volatile\"))"
+    ));
+    println!("cargo:rustc-check-cfg=cfg(clear_on_drop_asm)");
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=src/hide.c");
+
+    let asm = !cfg!(feature = "no_asm") && has_asm();
+    if asm {
+        println!("cargo:rustc-cfg=clear_on_drop_asm");
+    }
+
+    let hide = if cfg!(feature = "volatile") {
+        "volatile"
+    } else if asm {
+        "asm"
+    } else if !cfg!(feature = "no_cc") && has_cc() {
+        "cc"
+    } else {
+        "atomic"
+    };
+    println!("cargo:rustc-cfg=clear_on_drop_hide=\"{}\"", hide);
+}
+
+/// Whether the compiler accepts `core::arch::asm!` for the target: it
+/// was stabilized in Rust 1.59, for a few architectures.
+fn has_asm() -> bool {
+    probe(
+        r#"
+#![no_std]
+pub fn probe(ptr: *mut u8) {
+    unsafe { core::arch::asm!("/* {0} */", in(reg) ptr, options(nostack)) }
+}
+"#,
+    )
+}
+
+/// Compiles the dummy C function, and returns whether it worked.
+fn has_cc() -> bool {
+    match cc::Build::new()
+        .file("src/hide.c")
+        .try_compile("clear_on_drop")
+    {
+        Ok(()) => true,
+        Err(err) => {
+            println!(
+                "cargo:warning=cannot compile src/hide.c ({}), falling back to atomics",
+                err
+            );
+            false
+        }
+    }
+}
+
+/// Returns whether `code` compiles as a library for the target.
+fn probe(code: &str) -> bool {
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    let path = out_dir.join("probe.rs");
+    if fs::write(&path, code).is_err() {
+        return false;
+    }
+
+    let rustc = env::var_os("RUSTC").unwrap_or_else(|| "rustc".into());
+    let mut command = Command::new(rustc);
+    command
+        .arg("--crate-type=lib")
+        .arg("--crate-name=clear_on_drop_probe")
+        .arg("--edition=2018")
+        .arg("--emit=metadata")
+        .arg("--out-dir")
+        .arg(&out_dir)
+        .arg(&path)
+        .stderr(Stdio::null());
+    if let Some(target) = env::var_os("TARGET") {
+        command.arg("--target").arg(target);
+    }
+    if let Ok(flags) = env::var("CARGO_ENCODED_RUSTFLAGS") {
+        command.args(flags.split('\x1f').filter(|flag| !flag.is_empty()));
     }
+    matches!(command.status(), Ok(ref status) if status.success())
 }
@@ -0,0 +1,414 @@
+//! Derive macros for the `clear_on_drop` crate.
+//!
+//! This crate is not meant to be used directly; enable the `derive`
+//! feature of `clear_on_drop` instead, and use the macros re-exported
+//! from its `clear` module.
+//!
+//! # `#[derive(ZeroSafe)]`
+//!
+//! Implements `ZeroSafe` for a type, after checking that all-bits-zero
+//! is a valid value for it:
+//!
+//! * for a struct, every field must be `ZeroSafe`;
+//! * for an enum, it must have a `#[repr(C)]` or primitive
+//!   representation, a variant whose discriminant is zero, and every
+//!   field of that variant must be `ZeroSafe`.
+//!
+//! ```
+//! # use clear_on_drop::clear::ZeroSafe;
+//! #[derive(ZeroSafe)]
+//! #[repr(C)]
+//! struct KeySchedule {
+//!     round_keys: [u32; 60],
+//!     rounds: usize,
+//! }
+//!
+//! #[derive(ZeroSafe)]
+//! #[repr(u8)]
+//! enum State {
+//!     Empty,
+//!     Loaded,
+//! }
+//! ```
+//!
+//! Fields which are not `ZeroSafe` are rejected:
+//!
+//! ```compile_fail
+//! # use clear_on_drop::clear::ZeroSafe;
+//! #[derive(ZeroSafe)]
+//! struct Handle {
+//!     name: &'static str,
+//! }
+//! ```
+//!
+//! So are enums without a zero discriminant:
+//!
+//! ```compile_fail
+//! # use clear_on_drop::clear::ZeroSafe;
+//! #[derive(ZeroSafe)]
+//! #[repr(u8)]
+//! enum State {
+//!     Empty = 1,
+//!     Loaded,
+//! }
+//! ```
+//!
+//! # `#[derive(InitializableFromZeroed)]`
+//!
+//! Implements `InitializableFromZeroed` for a type which does not
+//! implement `Default`, so that it can be used with `Clear` and
+//! `ClearOnDrop`. Every field must either be `ZeroSafe`, or have a
+//! `#[clear(...)]` attribute saying how to initialize it after it has
+//! been zeroed:
+//!
+//! * `#[clear(default)]` initializes the field with `Default::default()`;
+//! * `#[clear(init = expr)]` initializes the field with `expr`.
+//!
+//! ```
+//! # use clear_on_drop::clear::{Clear, InitializableFromZeroed};
+//! #[derive(InitializableFromZeroed)]
+//! #[repr(C)]
+//! struct Cipher {
+//!     round_keys: [u32; 60],
+//!     #[clear(init = 14)]
+//!     rounds: u32,
+//!     #[clear(default)]
+//!     label: String,
+//! }
+//!
+//! let mut cipher = Cipher {
+//!     round_keys: [0x41414141; 60],
+//!     rounds: 10,
+//!     label: "aes-128".into(),
+//! };
+//! cipher.clear();
+//! assert_eq!(&cipher.round_keys[..], &[0; 60][..]);
+//! assert_eq!(cipher.rounds, 14);
+//! assert_eq!(cipher.label, "");
+//! ```
+//!
+//! Fields with a `#[clear(...)]` attribute must also implement
+//! `DeepClear`, which is used to wipe any heap memory they own before
+//! `Clear` drops them. In the example above, the contents of `label`
+//! are overwritten before its buffer is freed.
+//!
+//! Enums are accepted under the same conditions as `#[derive(ZeroSafe)]`.
+//!
+//! # `#[derive(DeepClear)]`
+//!
+//! Implements `DeepClear` for a struct or enum, by calling `deep_clear`
+//! on each of its fields, which must all implement `DeepClear`.
+//!
+//! ```
+//! # use clear_on_drop::clear::DeepClear;
+//! #[derive(DeepClear)]
+//! struct Credentials {
+//!     user: String,
+//!     password: Vec<u8>,
+//! }
+//!
+//! let mut credentials = Credentials {
+//!     user: "root".into(),
+//!     password: b"hunter2".to_vec(),
+//! };
+//! credentials.deep_clear();
+//! assert_eq!(credentials.user, "\0\0\0\0");
+//! assert_eq!(credentials.password, [0; 7]);
+//! ```
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote, quote_spanned};
+use syn::spanned::Spanned;
+use syn::{
+    parse_macro_input, parse_quote, Attribute, Data, DataEnum, DeriveInput, Error, Expr, ExprLit,
+    ExprUnary, Fields, Lit, Member, Result, Type, UnOp,
+};
+
+/// Derives `ZeroSafe` for a struct or enum.
+#[proc_macro_derive(ZeroSafe)]
+pub fn derive_zero_safe(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_zero_safe(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// Derives `InitializableFromZeroed` for a struct or enum.
+#[proc_macro_derive(InitializableFromZeroed, attributes(clear))]
+pub fn derive_initializable_from_zeroed(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_initializable_from_zeroed(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// Derives `DeepClear` for a struct or enum.
+#[proc_macro_derive(DeepClear)]
+pub fn derive_deep_clear(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_deep_clear(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn expand_zero_safe(input: &DeriveInput) -> Result<TokenStream2> {
+    let zero_safe = match input.data {
+        Data::Struct(ref data) => data.fields.iter().map(|f| f.ty.clone()).collect(),
+        Data::Enum(ref data) => zero_variant_types(input, data)?,
+        Data::Union(_) => {
+            return Err(Error::new_spanned(
+                input,
+                "ZeroSafe cannot be derived for unions",
+            ))
+        }
+    };
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, _) = input.generics.split_for_impl();
+    let where_clause = where_clause(input, &zero_safe, &[]);
+    Ok(quote! {
+        unsafe impl #impl_generics ::clear_on_drop::clear::ZeroSafe
+            for #name #ty_generics #where_clause {}
+    })
+}
+
+fn expand_initializable_from_zeroed(input: &DeriveInput) -> Result<TokenStream2> {
+    let mut zero_safe = Vec::new();
+    let mut inits = Vec::new();
+    match input.data {
+        Data::Struct(ref data) => {
+            for (i, field) in data.fields.iter().enumerate() {
+                let member = match field.ident {
+                    Some(ref ident) => Member::Named(ident.clone()),
+                    None => Member::Unnamed(i.into()),
+                };
+                match field_init(&field.attrs)? {
+                    Some(init) => inits.push((member, field.ty.clone(), init)),
+                    None => zero_safe.push(field.ty.clone()),
+                }
+            }
+        }
+        Data::Enum(ref data) => zero_safe = zero_variant_types(input, data)?,
+        Data::Union(_) => {
+            return Err(Error::new_spanned(
+                input,
+                "InitializableFromZeroed cannot be derived for unions",
+            ))
+        }
+    }
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, _) = input.generics.split_for_impl();
+    let deep_clear: Vec<_> = inits.iter().map(|(_, ty, _)| ty.clone()).collect();
+    let where_clause = where_clause(input, &zero_safe, &deep_clear);
+    let place = if inits.is_empty() {
+        quote!(_place)
+    } else {
+        quote!(place)
+    };
+    let heap = inits.iter().map(|(member, _, _)| member);
+    let writes = inits.iter().map(|(member, _, init)| {
+        quote_spanned! {init.span()=>
+            ::clear_on_drop::__private::write(
+                ::clear_on_drop::__private::addr_of_mut!((*place).#member),
+                #init,
+            );
+        }
+    });
+    Ok(quote! {
+        impl #impl_generics ::clear_on_drop::clear::InitializableFromZeroed
+            for #name #ty_generics #where_clause
+        {
+            #[inline]
+            unsafe fn initialize(#place: *mut Self) {
+                #[allow(unused_unsafe)]
+                unsafe {
+                    #(#writes)*
+                }
+            }
+
+            #[inline]
+            fn clear_heap(&mut self) {
+                #(
+                    ::clear_on_drop::clear::DeepClear::deep_clear(&mut self.#heap);
+                )*
+            }
+        }
+    })
+}
+
+fn expand_deep_clear(input: &DeriveInput) -> Result<TokenStream2> {
+    let mut deep_clear = Vec::new();
+    let body = match input.data {
+        Data::Struct(ref data) => {
+            let members: Vec<_> = data.fields.members().collect();
+            deep_clear.extend(data.fields.iter().map(|f| f.ty.clone()));
+            quote! {
+                #(
+                    ::clear_on_drop::clear::DeepClear::deep_clear(&mut self.#members);
+                )*
+            }
+        }
+        Data::Enum(ref data) => {
+            let arms = data.variants.iter().map(|variant| {
+                let ident = &variant.ident;
+                let members: Vec<_> = variant.fields.members().collect();
+                let bindings: Vec<_> = (0..members.len())
+                    .map(|i| format_ident!("__field{}", i))
+                    .collect();
+                deep_clear.extend(variant.fields.iter().map(|f| f.ty.clone()));
+                quote! {
+                    Self::#ident { #(#members: ref mut #bindings,)* } => {
+                        #(
+                            ::clear_on_drop::clear::DeepClear::deep_clear(#bindings);
+                        )*
+                    }
+                }
+            });
+            let arms: Vec<_> = arms.collect();
+            quote! {
+                match *self {
+                    #(#arms)*
+                }
+            }
+        }
+        Data::Union(_) => {
+            return Err(Error::new_spanned(
+                input,
+                "DeepClear cannot be derived for unions",
+            ))
+        }
+    };
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, _) = input.generics.split_for_impl();
+    let where_clause = where_clause(input, &[], &deep_clear);
+    Ok(quote! {
+        impl #impl_generics ::clear_on_drop::clear::DeepClear
+            for #name #ty_generics #where_clause
+        {
+            #[inline]
+            fn deep_clear(&mut self) {
+                #body
+            }
+        }
+    })
+}
+
+/// Adds a `ZeroSafe` bound for each of `zero_safe`, and a `DeepClear`
+/// bound for each of `deep_clear`, to the input's own where clause. For
+/// non-generic types, a failed bound is reported as an error on the
+/// offending field.
+fn where_clause(input: &DeriveInput, zero_safe: &[Type], deep_clear: &[Type]) -> TokenStream2 {
+    let predicates = input
+        .generics
+        .where_clause
+        .iter()
+        .flat_map(|w| w.predicates.iter())
+        .map(|p| quote!(#p));
+    let zero_safe = zero_safe
+        .iter()
+        .map(|ty| quote_spanned!(ty.span()=> #ty: ::clear_on_drop::clear::ZeroSafe));
+    let deep_clear = deep_clear
+        .iter()
+        .map(|ty| quote_spanned!(ty.span()=> #ty: ::clear_on_drop::clear::DeepClear));
+    quote!(where #(#predicates,)* #(#zero_safe,)* #(#deep_clear,)*)
+}
+
+/// Parses the `#[clear(...)]` attribute of a field, returning the
+/// expression which initializes it after being zeroed.
+fn field_init(attrs: &[Attribute]) -> Result<Option<Expr>> {
+    let mut init = None;
+    for attr in attrs.iter().filter(|a| a.path().is_ident("clear")) {
+        attr.parse_nested_meta(|meta| {
+            if init.is_some() {
+                return Err(meta.error("duplicate field initializer"));
+            }
+            if meta.path.is_ident("default") {
+                init = Some(parse_quote!(::clear_on_drop::__private::Default::default()));
+                Ok(())
+            } else if meta.path.is_ident("init") {
+                init = Some(meta.value()?.parse()?);
+                Ok(())
+            } else {
+                Err(meta.error("expected `default` or `init = ...`"))
+            }
+        })?;
+    }
+    Ok(init)
+}
+
+/// Finds the variant of an enum which all-bits-zero represents, and
+/// returns the types of its fields.
+fn zero_variant_types(input: &DeriveInput, data: &DataEnum) -> Result<Vec<Type>> {
+    if !has_defined_repr(&input.attrs)? {
+        return Err(Error::new_spanned(
+            &input.ident,
+            "enum must be #[repr(C)] or have a primitive representation \
+             for all-bits-zero to be a valid value",
+        ));
+    }
+
+    let mut discriminant: i128 = 0;
+    for variant in &data.variants {
+        if let Some((_, ref expr)) = variant.discriminant {
+            discriminant = literal_discriminant(expr)?;
+        }
+        if discriminant == 0 {
+            return Ok(match variant.fields {
+                Fields::Unit => Vec::new(),
+                ref fields => fields.iter().map(|f| f.ty.clone()).collect(),
+            });
+        }
+        discriminant = discriminant.wrapping_add(1);
+    }
+
+    Err(Error::new_spanned(
+        &input.ident,
+        "enum has no variant with a zero discriminant",
+    ))
+}
+
+fn has_defined_repr(attrs: &[Attribute]) -> Result<bool> {
+    const REPRS: &[&str] = &[
+        "C", "u8", "i8", "u16", "i16", "u32", "i32", "u64", "i64", "u128", "i128", "usize", "isize",
+    ];
+
+    let mut found = false;
+    for attr in attrs.iter().filter(|a| a.path().is_ident("repr")) {
+        attr.parse_nested_meta(|meta| {
+            if REPRS.iter().any(|r| meta.path.is_ident(r)) {
+                found = true;
+            }
+            // Skip the arguments of `align(N)` and similar.
+            if meta.input.peek(syn::token::Paren) {
+                let _: TokenStream2 = meta.input.parse()?;
+            }
+            Ok(())
+        })?;
+    }
+    Ok(found)
+}
+
+fn literal_discriminant(expr: &Expr) -> Result<i128> {
+    match *expr {
+        Expr::Lit(ExprLit {
+            lit: Lit::Int(ref int),
+            ..
+        }) => int.base10_parse(),
+        Expr::Unary(ExprUnary {
+            op: UnOp::Neg(_),
+            ref expr,
+            ..
+        }) => literal_discriminant(expr).map(|d| -d),
+        Expr::Group(ref group) => literal_discriminant(&group.expr),
+        Expr::Paren(ref paren) => literal_discriminant(&paren.expr),
+        _ => Err(Error::new_spanned(
+            expr,
+            "explicit discriminants must be integer literals",
+        )),
+    }
+}
@@ -0,0 +1,218 @@
+extern crate clear_on_drop;
+
+use std::marker::PhantomData;
+use std::mem;
+use std::num::{NonZeroU32, Wrapping};
+use std::slice;
+
+use clear_on_drop::clear::{Clear, DeepClear, InitializableFromZeroed, ZeroSafe};
+use clear_on_drop::ClearOnDrop;
+
+fn as_bytes<T: ?Sized>(x: &T) -> &[u8] {
+    unsafe { slice::from_raw_parts(x as *const T as *const u8, mem::size_of_val(x)) }
+}
+
+#[derive(Debug, PartialEq, ZeroSafe)]
+#[repr(C)]
+struct Schedule {
+    keys: [u32; 4],
+    rounds: u8,
+}
+
+#[derive(Debug, PartialEq, ZeroSafe)]
+struct Pair(u64, i16);
+
+#[derive(Debug, PartialEq, ZeroSafe)]
+#[repr(u8)]
+enum Mode {
+    Encrypt = 2,
+    None = 0,
+    Decrypt,
+}
+
+#[derive(ZeroSafe)]
+#[repr(C, u16)]
+enum Slot {
+    Empty(u16),
+    #[allow(dead_code)]
+    Full(&'static str),
+}
+
+#[derive(InitializableFromZeroed)]
+struct Cipher {
+    schedule: Schedule,
+    #[clear(init = 10)]
+    rounds: u32,
+    #[clear(default)]
+    label: String,
+}
+
+#[derive(InitializableFromZeroed)]
+struct Wrapper<T>(T, #[clear(init = 1)] u8);
+
+#[test]
+fn slice_of_struct() {
+    let mut place = [
+        Schedule {
+            keys: [0x41414141; 4],
+            rounds: 0x41,
+        },
+        Schedule {
+            keys: [0x42424242; 4],
+            rounds: 0x42,
+        },
+    ];
+    place[..].clear();
+    assert!(!as_bytes(&place[..]).iter().any(|&b| b != 0));
+}
+
+#[test]
+fn slice_of_tuple_struct() {
+    let mut place = [Pair(1, 2), Pair(3, 4)];
+    place[..].clear();
+    assert_eq!(place, [Pair(0, 0), Pair(0, 0)]);
+}
+
+#[test]
+fn slice_of_enum() {
+    let mut place = [Mode::Encrypt, Mode::Decrypt];
+    place[..].clear();
+    assert_eq!(place, [Mode::None, Mode::None]);
+}
+
+#[test]
+fn slice_of_data_enum() {
+    let mut place = [Slot::Full("secret"), Slot::Empty(7)];
+    place[..].clear();
+    for slot in &place {
+        match *slot {
+            Slot::Empty(n) => assert_eq!(n, 0),
+            Slot::Full(_) => panic!("not cleared"),
+        }
+    }
+}
+
+#[test]
+fn struct_with_initializers() {
+    let mut place = Cipher {
+        schedule: Schedule {
+            keys: [0x41414141; 4],
+            rounds: 0x41,
+        },
+        rounds: 14,
+        label: "aes".into(),
+    };
+    {
+        let _clear = ClearOnDrop::new(&mut place);
+    }
+    assert_eq!(place.schedule.keys, [0; 4]);
+    assert_eq!(place.schedule.rounds, 0);
+    assert_eq!(place.rounds, 10);
+    assert_eq!(place.label, "");
+}
+
+#[test]
+fn generic_struct() {
+    let mut place = Wrapper([0x41u8; 8], 0x41);
+    place.clear();
+    assert_eq!(place.0, [0; 8]);
+    assert_eq!(place.1, 1);
+}
+
+#[derive(DeepClear)]
+struct Credentials {
+    user: String,
+    keys: [Vec<u8>; 2],
+}
+
+#[derive(DeepClear)]
+enum Secret {
+    Empty,
+    Password(String),
+    Key { data: Box<[u8]> },
+}
+
+#[derive(DeepClear)]
+struct Handle {
+    ptr: *const u8,
+    id: Option<NonZeroU32>,
+    counter: Wrapping<u64>,
+    pair: (u8, String),
+    _marker: PhantomData<u8>,
+}
+
+#[derive(InitializableFromZeroed)]
+struct Token {
+    #[clear(default)]
+    value: Option<String>,
+}
+
+#[test]
+fn struct_clear_heap() {
+    let mut place = Cipher {
+        schedule: Schedule {
+            keys: [0x41414141; 4],
+            rounds: 0x41,
+        },
+        rounds: 14,
+        label: "aes".into(),
+    };
+    InitializableFromZeroed::clear_heap(&mut place);
+    assert_eq!(place.label, "\0\0\0");
+}
+
+#[test]
+fn struct_deep_clear() {
+    let mut place = Credentials {
+        user: "root".into(),
+        keys: [vec![0x41; 4], vec![0x42; 2]],
+    };
+    place.deep_clear();
+    assert_eq!(place.user, "\0\0\0\0");
+    assert_eq!(place.keys, [vec![0; 4], vec![0; 2]]);
+}
+
+#[test]
+fn enum_deep_clear() {
+    let mut place = [
+        Secret::Empty,
+        Secret::Password("hunter2".into()),
+        Secret::Key {
+            data: vec![0x41; 4].into_boxed_slice(),
+        },
+    ];
+    place.deep_clear();
+    match place[1] {
+        Secret::Password(ref p) => assert_eq!(p, "\0\0\0\0\0\0\0"),
+        _ => unreachable!(),
+    }
+    match place[2] {
+        Secret::Key { ref data } => assert_eq!(&data[..], &[0; 4][..]),
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn struct_deep_clear_fields() {
+    let mut place = Handle {
+        ptr: b"secret".as_ptr(),
+        id: NonZeroU32::new(7),
+        counter: Wrapping(0x41),
+        pair: (0x41, "root".into()),
+        _marker: PhantomData,
+    };
+    place.deep_clear();
+    assert!(place.ptr.is_null());
+    assert_eq!(place.id, None);
+    assert_eq!(place.counter, Wrapping(0));
+    assert_eq!(place.pair, (0, "\0\0\0\0".into()));
+}
+
+#[test]
+fn option_field_clear_heap() {
+    let mut place = Token {
+        value: Some("hunter2".into()),
+    };
+    InitializableFromZeroed::clear_heap(&mut place);
+    assert_eq!(place.value.as_ref().unwrap(), "\0\0\0\0\0\0\0");
+}
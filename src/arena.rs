@@ -0,0 +1,418 @@
+use core::alloc::Layout;
+use core::cmp;
+use core::fmt;
+use core::mem;
+use core::ops::{Deref, DerefMut};
+use core::ptr;
+use core::slice;
+
+use clear::{Clear, InitializableFromZeroed, ZeroSafe};
+use locked::LockOptions;
+use sys::{page_size, OsError, Region};
+
+/// The unit of allocation within an arena, in bytes.
+const GRANULE: usize = 16;
+
+const BITS: usize = mem::size_of::<usize>() * 8;
+
+/// Rounds `size` up to whole granules.
+#[inline]
+fn round_to_granules(size: usize) -> usize {
+    (size + GRANULE - 1) & !(GRANULE - 1)
+}
+
+/// Returns the size of the bitmap for `granules` granules, rounded up
+/// to whole granules.
+#[inline]
+fn bitmap_size(granules: usize) -> usize {
+    round_to_granules((granules / BITS + 1) * mem::size_of::<usize>())
+}
+
+/// A region of locked memory shared by many small secrets.
+///
+/// Every `LockedBox` takes at least a whole page of locked memory, and
+/// the amount of memory a process can lock is often limited to a few
+/// dozen pages (see `RLIMIT_MEMLOCK`). An arena locks a single region,
+/// and hands out parts of it as `ArenaBox` handles, which can be used
+/// as the place of a `ClearOnDrop`. The memory of each handle is cleared
+/// when it is dropped, and the whole region is cleared when the arena
+/// is reset or dropped.
+///
+/// Like a `LockedBox`, the region is excluded from core dumps and wiped
+/// in forked children, unless disabled in the `LockOptions`.
+///
+/// # Example
+///
+/// ```
+/// # use clear_on_drop::{ClearOnDrop, SecretArena};
+/// let arena = SecretArena::new(4096).unwrap();
+/// let mut key = ClearOnDrop::new(arena.alloc_zeroed::<[u8; 32]>().unwrap());
+/// let mut iv = ClearOnDrop::new(arena.alloc_zeroed::<[u8; 16]>().unwrap());
+/// key.copy_from_slice(&[0x41; 32]);
+/// iv.copy_from_slice(&[0x42; 16]);
+/// ```
+pub struct SecretArena {
+    region: Region,
+    data: *mut u8,
+    granules: usize,
+}
+
+impl SecretArena {
+    /// Creates an arena with room for at least `capacity` bytes.
+    ///
+    /// The capacity is rounded up to use all the pages the arena locks;
+    /// each allocation takes a multiple of 16 bytes, plus padding for
+    /// alignment.
+    #[inline]
+    pub fn new(capacity: usize) -> Result<Self, OsError> {
+        Self::with_options(capacity, LockOptions::new())
+    }
+
+    /// Creates an arena with room for at least `capacity` bytes, with
+    /// the given options.
+    pub fn with_options(capacity: usize, options: LockOptions) -> Result<Self, OsError> {
+        let granules = capacity
+            .checked_add(GRANULE - 1)
+            .expect("capacity overflow")
+            / GRANULE;
+        let size = (granules * GRANULE)
+            .checked_add(bitmap_size(granules))
+            .expect("capacity overflow");
+        let region = options.map(size)?;
+
+        let mut granules = region.len() / GRANULE;
+        while bitmap_size(granules) + granules * GRANULE > region.len() {
+            granules -= 1;
+        }
+        let data = unsafe { region.as_ptr().add(bitmap_size(granules)) };
+        Ok(SecretArena {
+            region,
+            data,
+            granules,
+        })
+    }
+
+    /// Returns the number of bytes the arena can hold.
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.granules * GRANULE
+    }
+
+    /// Moves `value` into the arena, or returns `None` if there is no
+    /// room left for it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `T` is aligned to more than a page.
+    pub fn alloc<T>(&self, value: T) -> Option<ArenaBox<'_, T>> {
+        let ptr = self.reserve(Layout::new::<T>())? as *mut T;
+        unsafe {
+            ptr::write(ptr, value);
+        }
+        Some(ArenaBox { arena: self, ptr })
+    }
+
+    /// Allocates room for a `T`, and initializes it in place from
+    /// all-bits-zero, or returns `None` if there is no room left.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `T` is aligned to more than a page.
+    pub fn alloc_zeroed<T>(&self) -> Option<ArenaBox<'_, T>>
+    where
+        T: InitializableFromZeroed,
+    {
+        let ptr = self.reserve(Layout::new::<T>())? as *mut T;
+        unsafe {
+            T::initialize(ptr);
+        }
+        Some(ArenaBox { arena: self, ptr })
+    }
+
+    /// Allocates room for `len` elements, all set to zero, or returns
+    /// `None` if there is no room left.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `T` is aligned to more than a page.
+    pub fn alloc_slice_zeroed<T>(&self, len: usize) -> Option<ArenaBox<'_, [T]>>
+    where
+        T: ZeroSafe,
+    {
+        let ptr = self.reserve(Layout::array::<T>(len).ok()?)? as *mut T;
+        Some(ArenaBox {
+            arena: self,
+            ptr: ptr::slice_from_raw_parts_mut(ptr, len),
+        })
+    }
+
+    /// Copies `src` into the arena, or returns `None` if there is no
+    /// room left for it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `T` is aligned to more than a page.
+    pub fn alloc_slice_copy<T>(&self, src: &[T]) -> Option<ArenaBox<'_, [T]>>
+    where
+        T: Copy,
+    {
+        let ptr = self.reserve(Layout::for_value(src))? as *mut T;
+        unsafe {
+            ptr::copy_nonoverlapping(src.as_ptr(), ptr, src.len());
+        }
+        Some(ArenaBox {
+            arena: self,
+            ptr: ptr::slice_from_raw_parts_mut(ptr, src.len()),
+        })
+    }
+
+    /// Clears the whole arena, making all of its room available again.
+    ///
+    /// Values which were leaked with `mem::forget` are cleared without
+    /// being dropped.
+    #[inline]
+    pub fn reset(&mut self) {
+        // Clearing the bitmap too marks every granule as free.
+        self.region.wipe();
+    }
+
+    #[inline]
+    fn bitmap(&self) -> *mut usize {
+        self.region.as_ptr() as *mut usize
+    }
+
+    #[inline]
+    fn is_used(&self, index: usize) -> bool {
+        let word = unsafe { *self.bitmap().add(index / BITS) };
+        word & (1 << (index % BITS)) != 0
+    }
+
+    #[inline]
+    fn set_used(&self, index: usize, used: bool) {
+        unsafe {
+            let word = self.bitmap().add(index / BITS);
+            if used {
+                *word |= 1 << (index % BITS);
+            } else {
+                *word &= !(1 << (index % BITS));
+            }
+        }
+    }
+
+    /// Returns the number of granules taken by `size` bytes.
+    #[inline]
+    fn granules_for(size: usize) -> usize {
+        cmp::max(1, round_to_granules(size) / GRANULE)
+    }
+
+    /// Finds and marks as used the first run of free granules which
+    /// fits `layout`. Free granules are always zeroed.
+    fn reserve(&self, layout: Layout) -> Option<*mut u8> {
+        assert!(layout.align() <= page_size());
+        let count = Self::granules_for(layout.size());
+        let align = cmp::max(layout.align(), GRANULE);
+        let first = ((align - self.data as usize % align) % align) / GRANULE;
+        let step = align / GRANULE;
+
+        let mut start = first;
+        while start.checked_add(count)? <= self.granules {
+            match (start..start + count).rev().find(|&i| self.is_used(i)) {
+                None => {
+                    for index in start..start + count {
+                        self.set_used(index, true);
+                    }
+                    return Some(unsafe { self.data.add(start * GRANULE) });
+                }
+                Some(used) => {
+                    // Skip to the next aligned granule after the used one.
+                    start = first + ((used + 1 - first + step - 1) & !(step - 1));
+                }
+            }
+        }
+        None
+    }
+
+    /// Clears and frees the granules taken by `size` bytes at `ptr`.
+    fn release(&self, ptr: *mut u8, size: usize) {
+        let start = (ptr as usize - self.data as usize) / GRANULE;
+        let count = Self::granules_for(size);
+        unsafe { slice::from_raw_parts_mut(ptr, count * GRANULE) }.clear();
+        for index in start..start + count {
+            self.set_used(index, false);
+        }
+    }
+}
+
+impl Drop for SecretArena {
+    fn drop(&mut self) {
+        self.region.wipe();
+    }
+}
+
+impl fmt::Debug for SecretArena {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SecretArena")
+            .field("capacity", &self.capacity())
+            .finish()
+    }
+}
+
+// The arena is not Sync, since allocating and freeing through a shared
+// reference is not synchronized.
+unsafe impl Send for SecretArena {}
+
+/// A value or slice allocated from a `SecretArena`.
+///
+/// When dropped, the value is dropped, and its memory is cleared and
+/// returned to the arena.
+pub struct ArenaBox<'a, T: ?Sized + 'a> {
+    arena: &'a SecretArena,
+    ptr: *mut T,
+}
+
+impl<'a, T: ?Sized> Drop for ArenaBox<'a, T> {
+    fn drop(&mut self) {
+        unsafe {
+            let size = mem::size_of_val(&*self.ptr);
+            ptr::drop_in_place(self.ptr);
+            self.arena.release(self.ptr as *mut u8, size);
+        }
+    }
+}
+
+impl<'a, T: ?Sized> Deref for ArenaBox<'a, T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        unsafe { &*self.ptr }
+    }
+}
+
+impl<'a, T: ?Sized> DerefMut for ArenaBox<'a, T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.ptr }
+    }
+}
+
+impl<'a, T: ?Sized + fmt::Debug> fmt::Debug for ArenaBox<'a, T> {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SecretArena;
+    use ClearOnDrop;
+
+    use core::mem;
+    use core::slice;
+
+    #[derive(Default)]
+    struct Place {
+        data: [u32; 4],
+    }
+
+    #[derive(Default)]
+    #[repr(align(64))]
+    struct Aligned {
+        data: [u8; 3],
+    }
+
+    const DATA: [u32; 4] = [0x01234567, 0x89abcdef, 0xfedcba98, 0x76543210];
+
+    fn is_zero<T: ?Sized>(ptr: *const T) -> bool {
+        let bytes = unsafe { slice::from_raw_parts(ptr as *const u8, mem::size_of_val(&*ptr)) };
+        bytes.iter().all(|&b| b == 0)
+    }
+
+    #[test]
+    fn alloc() {
+        let arena = SecretArena::new(1).unwrap();
+        assert!(arena.capacity() > 4000);
+        let mut first = arena.alloc(Place { data: DATA }).unwrap();
+        let second = arena.alloc_zeroed::<Place>().unwrap();
+        let third = arena.alloc_slice_copy(&DATA[..3]).unwrap();
+        assert_eq!(first.data, DATA);
+        assert_eq!(second.data, [0; 4]);
+        assert_eq!(&third[..], &DATA[..3]);
+        first.data[0] = 0;
+        assert_eq!(second.data, [0; 4]);
+        assert_eq!(&third[..], &DATA[..3]);
+    }
+
+    #[test]
+    fn aligned() {
+        let arena = SecretArena::new(1).unwrap();
+        let _small = arena.alloc(1u8).unwrap();
+        let aligned = arena.alloc_zeroed::<Aligned>().unwrap();
+        assert_eq!(&*aligned as *const Aligned as usize % 64, 0);
+        assert_eq!(aligned.data, [0; 3]);
+        let _small = arena.alloc(1u8).unwrap();
+    }
+
+    #[test]
+    fn release_clears() {
+        let arena = SecretArena::new(1).unwrap();
+        let place = arena.alloc(Place { data: DATA }).unwrap();
+        let ptr = &*place as *const Place;
+        drop(place);
+        assert!(is_zero(ptr));
+        let again = arena.alloc_zeroed::<Place>().unwrap();
+        assert_eq!(&*again as *const Place, ptr);
+    }
+
+    #[test]
+    fn full() {
+        let arena = SecretArena::new(1).unwrap();
+        let slots = arena.capacity() / 16;
+        let mut keys: Vec<_> = (0..slots)
+            .map(|_| arena.alloc([0x41u8; 16]).unwrap())
+            .collect();
+        assert!(arena.alloc(0u8).is_none());
+        assert!(arena
+            .alloc_slice_zeroed::<u8>(arena.capacity() + 1)
+            .is_none());
+        keys.truncate(slots - 2);
+        assert!(arena.alloc([0u8; 32]).is_some());
+        assert!(arena.alloc([0u8; 33]).is_none());
+    }
+
+    #[test]
+    fn drops_value() {
+        let value = ::std::rc::Rc::new(());
+        let arena = SecretArena::new(1).unwrap();
+        let place = arena.alloc(value.clone()).unwrap();
+        assert_eq!(::std::rc::Rc::strong_count(&value), 2);
+        drop(place);
+        assert_eq!(::std::rc::Rc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn into_clear_on_drop() {
+        let arena = SecretArena::new(1).unwrap();
+        let mut clear = ClearOnDrop::new(arena.alloc_zeroed::<Place>().unwrap());
+        clear.data = DATA;
+        let place = ClearOnDrop::into_place(clear);
+        assert_eq!(place.data, [0; 4]);
+    }
+
+    #[test]
+    fn reset() {
+        let mut arena = SecretArena::new(1).unwrap();
+        let ptr = {
+            let key = arena.alloc_slice_copy(&DATA).unwrap();
+            let ptr = &*key as *const [u32];
+            mem::forget(key);
+            ptr
+        };
+        assert!(!is_zero(ptr));
+        arena.reset();
+        assert!(is_zero(ptr));
+        let key = arena.alloc_slice_zeroed::<u32>(4).unwrap();
+        assert_eq!(&*key as *const [u32], ptr);
+    }
+}
@@ -0,0 +1,242 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use libc;
+
+use sys::{parse_decimal, read_file, OsError};
+
+/// Whether a region of secret memory is locked into RAM.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Residency {
+    /// The region does not hold secrets, or is not set up yet.
+    Other,
+    /// The region is locked into RAM.
+    Locked,
+    /// The region holds secrets, but could not be locked.
+    Unlocked,
+}
+
+static LOCKED_BYTES: AtomicUsize = AtomicUsize::new(0);
+static UNLOCKED_BYTES: AtomicUsize = AtomicUsize::new(0);
+static LOCK_FAILURES: AtomicUsize = AtomicUsize::new(0);
+
+#[inline]
+fn counter(residency: Residency) -> Option<&'static AtomicUsize> {
+    match residency {
+        Residency::Other => None,
+        Residency::Locked => Some(&LOCKED_BYTES),
+        Residency::Unlocked => Some(&UNLOCKED_BYTES),
+    }
+}
+
+/// Counts `len` more bytes of secret memory.
+#[inline]
+pub(crate) fn add(residency: Residency, len: usize) {
+    if let Some(counter) = counter(residency) {
+        counter.fetch_add(len, Ordering::Relaxed);
+    }
+}
+
+/// Counts `len` fewer bytes of secret memory.
+#[inline]
+pub(crate) fn sub(residency: Residency, len: usize) {
+    if let Some(counter) = counter(residency) {
+        counter.fetch_sub(len, Ordering::Relaxed);
+    }
+}
+
+/// Counts a failure to lock memory.
+#[inline]
+pub(crate) fn add_failure() {
+    LOCK_FAILURES.fetch_add(1, Ordering::Relaxed);
+}
+
+/// How much of the secret memory allocated by this crate is locked.
+///
+/// This covers the memory of every `LockedBox`, `LockedSlice`,
+/// `GuardedBox`, `SecretMemBox` and `SecretArena` currently alive, in
+/// whole pages.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct LockReport {
+    /// The number of bytes locked into RAM.
+    pub locked_bytes: usize,
+    /// The number of bytes which could not be locked, and were used
+    /// anyway as allowed by their `LockPolicy`.
+    pub unlocked_bytes: usize,
+    /// The number of times locking memory has failed since the process
+    /// started, whatever the `LockPolicy`.
+    pub lock_failures: usize,
+}
+
+/// Returns how much of the secret memory allocated by this crate is
+/// locked.
+///
+/// # Example
+///
+/// ```
+/// # use clear_on_drop::{lock_report, LockOptions, LockPolicy, LockedBox};
+/// let options = LockOptions::new().lock_policy(LockPolicy::Fallback);
+/// let key = LockedBox::new_with_options([0u8; 32], options).unwrap();
+/// let report = lock_report();
+/// assert!(report.locked_bytes + report.unlocked_bytes >= 32);
+/// if report.unlocked_bytes > 0 {
+///     // Some secrets may be written to swap.
+/// }
+/// ```
+pub fn lock_report() -> LockReport {
+    LockReport {
+        locked_bytes: LOCKED_BYTES.load(Ordering::Relaxed),
+        unlocked_bytes: UNLOCKED_BYTES.load(Ordering::Relaxed),
+        lock_failures: LOCK_FAILURES.load(Ordering::Relaxed),
+    }
+}
+
+/// How much memory the process may lock into RAM.
+///
+/// Processes with the `CAP_IPC_LOCK` capability are not restricted by
+/// the limits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MemlockBudget {
+    /// The `RLIMIT_MEMLOCK` soft limit in bytes, or `None` if unlimited.
+    pub limit: Option<usize>,
+    /// The `RLIMIT_MEMLOCK` hard limit in bytes, up to which the soft
+    /// limit can be raised, or `None` if unlimited.
+    pub max_limit: Option<usize>,
+    /// The number of bytes the whole process has locked, including
+    /// memory not allocated by this crate, or `None` if it cannot be
+    /// read from `/proc/self/status`.
+    pub locked: Option<usize>,
+}
+
+impl MemlockBudget {
+    /// Returns the number of bytes which can still be locked without
+    /// raising the limit, or `None` if unlimited.
+    ///
+    /// If the memory locked by the process is unknown, only the memory
+    /// locked by this crate is taken into account.
+    pub fn available(&self) -> Option<usize> {
+        let locked = self.locked.unwrap_or_else(|| lock_report().locked_bytes);
+        self.limit.map(|limit| limit.saturating_sub(locked))
+    }
+}
+
+/// Returns how much memory the process may lock into RAM.
+///
+/// # Example
+///
+/// ```
+/// # use clear_on_drop::memlock_budget;
+/// let budget = memlock_budget().unwrap();
+/// if let Some(available) = budget.available() {
+///     println!("{} more bytes can be locked", available);
+/// }
+/// ```
+pub fn memlock_budget() -> Result<MemlockBudget, OsError> {
+    let mut rlimit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    if unsafe { libc::getrlimit(libc::RLIMIT_MEMLOCK, &mut rlimit) } != 0 {
+        return Err(OsError::last("getrlimit"));
+    }
+    Ok(MemlockBudget {
+        limit: limit_bytes(rlimit.rlim_cur),
+        max_limit: limit_bytes(rlimit.rlim_max),
+        locked: locked_bytes(),
+    })
+}
+
+#[inline]
+fn limit_bytes(limit: libc::rlim_t) -> Option<usize> {
+    if limit == libc::RLIM_INFINITY {
+        None
+    } else {
+        Some(limit as usize)
+    }
+}
+
+/// Reads the `VmLck` line of `/proc/self/status`.
+fn locked_bytes() -> Option<usize> {
+    let mut buf = [0u8; 8192];
+    let status = read_file(b"/proc/self/status\0", &mut buf)?;
+    parse_status_kb(status, b"VmLck:").map(|kb| kb * 1024)
+}
+
+/// Finds a `key: value kB` line in the contents of a status file.
+fn parse_status_kb(status: &[u8], key: &[u8]) -> Option<usize> {
+    let line = status
+        .split(|&b| b == b'\n')
+        .find(|line| line.starts_with(key))?;
+    parse_decimal(&line[key.len()..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{lock_report, memlock_budget, parse_status_kb};
+    use libc;
+    use locked::{LockOptions, LockPolicy};
+    use sys::page_size;
+    use LockedBox;
+
+    #[test]
+    fn parse_status() {
+        let status = b"Name:\ttest\nVmPeak:\t  123 kB\nVmLck:\t      8 kB\nVmPin:\t0 kB\n";
+        assert_eq!(parse_status_kb(status, b"VmLck:"), Some(8));
+        assert_eq!(parse_status_kb(status, b"VmPeak:"), Some(123));
+        assert_eq!(parse_status_kb(status, b"VmSwap:"), None);
+        assert_eq!(parse_status_kb(b"VmLck:\t kB\n", b"VmLck:"), None);
+    }
+
+    #[test]
+    fn budget() {
+        let budget = memlock_budget().unwrap();
+        if let (Some(limit), Some(max_limit)) = (budget.limit, budget.max_limit) {
+            assert!(limit <= max_limit);
+        }
+        assert!(budget.locked.is_some());
+        assert!(budget.available() <= budget.limit);
+    }
+
+    #[test]
+    fn policy() {
+        // Lowers the limit in a child, so the other tests are unaffected;
+        // exits with 2 if locking still succeeds, as with CAP_IPC_LOCK.
+        let pid = unsafe { libc::fork() };
+        assert!(pid >= 0);
+        if pid == 0 {
+            let code = unsafe {
+                let limit = libc::rlimit {
+                    rlim_cur: 0,
+                    rlim_max: 0,
+                };
+                libc::setrlimit(libc::RLIMIT_MEMLOCK, &limit);
+                let before = lock_report();
+                match LockedBox::new([0u8; 64]) {
+                    Ok(_) => 2,
+                    Err(err) if err.call() != "mlock" => 1,
+                    Err(_) => {
+                        let options = LockOptions::new().lock_policy(LockPolicy::Fallback);
+                        let place = LockedBox::new_with_options([0u8; 64], options).unwrap();
+                        let report = lock_report();
+                        let ok = report.unlocked_bytes == before.unlocked_bytes + page_size()
+                            && report.lock_failures == before.lock_failures + 2;
+                        drop(place);
+                        if ok && lock_report().unlocked_bytes == before.unlocked_bytes {
+                            0
+                        } else {
+                            1
+                        }
+                    }
+                }
+            };
+            unsafe { libc::_exit(code) };
+        }
+        let mut status = 0;
+        assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+        assert!(libc::WIFEXITED(status));
+        match libc::WEXITSTATUS(status) {
+            0 => {}
+            2 => eprintln!("skipped: locking memory is not limited"),
+            _ => panic!("wrong lock report"),
+        }
+    }
+}
@@ -38,11 +38,51 @@
 //! }
 //! assert!(!as_bytes(&place).contains(&0x41));
 //! ```
+//!
+//! Fixed-size arrays of `ZeroSafe` elements are `ZeroSafe` for any
+//! length. Since the standard library implements `Default` only for
+//! short arrays, longer arrays are cleared as slices, or as fields of
+//! a type implementing `InitializableFromZeroed`:
+//!
+//! ```
+//! # use clear_on_drop::ClearOnDrop;
+//! let mut key = [0u8; 48];
+//! {
+//!     let mut clear = ClearOnDrop::new(&mut key[..]);
+//!     clear.copy_from_slice(&[0x41; 48]);
+//! }
+//! assert_eq!(&key[..], &[0; 48][..]);
+//! ```
+//!
+//! Arrays and slices whose elements are `Clear` but not `ZeroSafe` are
+//! cleared element by element with `ClearElements`:
+//!
+//! ```
+//! # use clear_on_drop::clear::ClearElements;
+//! let mut keys = [Some(0x41414141u32); 40];
+//! keys.clear_elements();
+//! assert_eq!(&keys[..], &[None; 40][..]);
+//! ```
 
+use core::marker::PhantomData;
 use core::mem;
+use core::num::Wrapping;
+use core::num::{NonZeroI128, NonZeroI16, NonZeroI32, NonZeroI64, NonZeroI8, NonZeroIsize};
+use core::num::{NonZeroU128, NonZeroU16, NonZeroU32, NonZeroU64, NonZeroU8, NonZeroUsize};
 use core::ptr;
+use core::ptr::NonNull;
 
-use hide::hide_mem_impl;
+#[cfg(feature = "alloc")]
+use alloc::boxed::Box;
+#[cfg(feature = "alloc")]
+use alloc::string::String;
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+
+use hide::{clear_mem, hide_mem_impl};
+
+#[cfg(feature = "derive")]
+pub use clear_on_drop_derive::{DeepClear, InitializableFromZeroed, ZeroSafe};
 
 /// An operation to completely overwrite a value, without leaking data.
 ///
@@ -60,12 +100,12 @@ where
 {
     #[inline]
     fn clear(&mut self) {
+        self.clear_heap();
         let size = mem::size_of_val(self);
         unsafe {
             let ptr = self as *mut Self;
             ptr::drop_in_place(ptr);
-            ptr::write_bytes(ptr as *mut u8, 0, size);
-            hide_mem_impl::<Self>(ptr);
+            clear_mem(ptr as *mut u8, size);
             Self::initialize(ptr);
         }
     }
@@ -73,6 +113,10 @@ where
 
 /// A type that can be initialized to a valid value, after being set to
 /// all-bits-zero.
+///
+/// This trait is implemented for every type which implements `Default`.
+/// With the `derive` feature, it can also be derived for types which do
+/// not; see the `clear_on_drop_derive` crate.
 pub trait InitializableFromZeroed {
     /// Called to initialize a place to a valid value, after it is set
     /// to all-bits-zero.
@@ -80,6 +124,18 @@ pub trait InitializableFromZeroed {
     /// If all-bits-zero is a valid value for a place, this method can
     /// be left empty.
     unsafe fn initialize(place: *mut Self);
+
+    /// Called by `Clear::clear` before the value is dropped, to wipe
+    /// any heap memory it owns before it is returned to the allocator.
+    ///
+    /// The default implementation does nothing, which is also what the
+    /// implementation for `Default` types does, including `Option<T>`,
+    /// `Vec<T>` and `String`. Types which own heap memory should forward
+    /// this to `DeepClear::deep_clear`, as the derive does for fields
+    /// with a `#[clear(...)]` attribute; for a `Default` type on its
+    /// own, use `DeepClearOnDrop` instead.
+    #[inline]
+    fn clear_heap(&mut self) {}
 }
 
 impl<T> InitializableFromZeroed for T
@@ -105,7 +161,224 @@ impl InitializableFromZeroed for str {
     unsafe fn initialize(_place: *mut Self) {}
 }
 
+/// An operation to clear every element of an array or slice in turn.
+///
+/// Arrays and slices of `ZeroSafe` elements are cleared as a whole with
+/// `Clear`; this trait also covers elements which are only `Clear`,
+/// such as an `Option<u32>` or a type deriving `InitializableFromZeroed`,
+/// for arrays of any length.
+pub trait ClearElements {
+    /// Completely overwrites every element.
+    fn clear_elements(&mut self);
+}
+
+impl<T: Clear> ClearElements for [T] {
+    #[inline]
+    fn clear_elements(&mut self) {
+        for x in self.iter_mut() {
+            x.clear();
+        }
+    }
+}
+
+impl<T: Clear, const N: usize> ClearElements for [T; N] {
+    #[inline]
+    fn clear_elements(&mut self) {
+        self[..].clear_elements();
+    }
+}
+
+/// An operation to overwrite a value and the heap memory it owns.
+///
+/// Dropping a `Vec<T>`, `String` or `Box<T>` returns its heap memory to
+/// the allocator without wiping it, so the blanket `Clear` only wipes
+/// the pointer to it. This trait instead overwrites the heap memory in
+/// place, including any spare capacity, and leaves the value valid, so
+/// it can be dropped or reused afterwards.
+///
+/// With the `derive` feature, this trait can be derived for types whose
+/// fields all implement it.
+///
+/// # Example
+///
+/// ```
+/// # use clear_on_drop::clear::DeepClear;
+/// let mut secret = Vec::with_capacity(64);
+/// secret.extend_from_slice(b"password");
+/// secret.deep_clear();
+/// assert_eq!(secret, [0; 8]);
+/// ```
+pub trait DeepClear {
+    /// Overwrites this value and any heap memory it owns with zeros,
+    /// without freeing anything.
+    fn deep_clear(&mut self);
+}
+
+macro_rules! zerosafe_impl_deepclear {
+    ($($T:ty)+) => {
+        $(
+            impl DeepClear for $T {
+                #[inline]
+                fn deep_clear(&mut self) {
+                    unsafe {
+                        ptr::write_bytes(self as *mut Self, 0, 1);
+                    }
+                }
+            }
+        )+
+    }
+}
+
+zerosafe_impl_deepclear! {
+    isize usize i8 u8 i16 u16 i32 u32 i64 u64 i128 u128
+    f32 f64 bool char
+}
+
+// All-bits-zero is `None` for these.
+zerosafe_impl_deepclear! {
+    Option<NonZeroIsize> Option<NonZeroUsize> Option<NonZeroI8> Option<NonZeroU8>
+    Option<NonZeroI16> Option<NonZeroU16> Option<NonZeroI32> Option<NonZeroU32>
+    Option<NonZeroI64> Option<NonZeroU64> Option<NonZeroI128> Option<NonZeroU128>
+}
+
+impl<T: ?Sized> DeepClear for *const T {
+    #[inline]
+    fn deep_clear(&mut self) {
+        unsafe {
+            ptr::write_bytes(self as *mut Self, 0, 1);
+        }
+    }
+}
+
+impl<T: ?Sized> DeepClear for *mut T {
+    #[inline]
+    fn deep_clear(&mut self) {
+        unsafe {
+            ptr::write_bytes(self as *mut Self, 0, 1);
+        }
+    }
+}
+
+impl DeepClear for () {
+    #[inline]
+    fn deep_clear(&mut self) {}
+}
+
+impl<T: ?Sized> DeepClear for PhantomData<T> {
+    #[inline]
+    fn deep_clear(&mut self) {}
+}
+
+impl<T: DeepClear> DeepClear for Wrapping<T> {
+    #[inline]
+    fn deep_clear(&mut self) {
+        self.0.deep_clear();
+    }
+}
+
+macro_rules! tuple_impl_deepclear {
+    ($($T:ident $i:tt)+) => {
+        impl<$($T: DeepClear),+> DeepClear for ($($T,)+) {
+            #[inline]
+            fn deep_clear(&mut self) {
+                $(self.$i.deep_clear();)+
+            }
+        }
+    }
+}
+
+tuple_impl_deepclear!(A 0);
+tuple_impl_deepclear!(A 0 B 1);
+tuple_impl_deepclear!(A 0 B 1 C 2);
+tuple_impl_deepclear!(A 0 B 1 C 2 D 3);
+tuple_impl_deepclear!(A 0 B 1 C 2 D 3 E 4);
+tuple_impl_deepclear!(A 0 B 1 C 2 D 3 E 4 F 5);
+tuple_impl_deepclear!(A 0 B 1 C 2 D 3 E 4 F 5 G 6);
+tuple_impl_deepclear!(A 0 B 1 C 2 D 3 E 4 F 5 G 6 H 7);
+tuple_impl_deepclear!(A 0 B 1 C 2 D 3 E 4 F 5 G 6 H 7 I 8);
+tuple_impl_deepclear!(A 0 B 1 C 2 D 3 E 4 F 5 G 6 H 7 I 8 J 9);
+tuple_impl_deepclear!(A 0 B 1 C 2 D 3 E 4 F 5 G 6 H 7 I 8 J 9 K 10);
+tuple_impl_deepclear!(A 0 B 1 C 2 D 3 E 4 F 5 G 6 H 7 I 8 J 9 K 10 L 11);
+
+impl<T: DeepClear, const N: usize> DeepClear for [T; N] {
+    #[inline]
+    fn deep_clear(&mut self) {
+        self[..].deep_clear();
+    }
+}
+
+impl<T: DeepClear> DeepClear for [T] {
+    #[inline]
+    fn deep_clear(&mut self) {
+        for x in self.iter_mut() {
+            x.deep_clear();
+        }
+        hide_mem_impl::<Self>(self);
+    }
+}
+
+impl DeepClear for str {
+    #[inline]
+    fn deep_clear(&mut self) {
+        // All-zeros is valid UTF-8.
+        unsafe { self.as_bytes_mut() }.deep_clear();
+    }
+}
+
+impl<T: DeepClear> DeepClear for Option<T> {
+    #[inline]
+    fn deep_clear(&mut self) {
+        if let Some(ref mut x) = *self {
+            x.deep_clear();
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T: ?Sized + DeepClear> DeepClear for Box<T> {
+    #[inline]
+    fn deep_clear(&mut self) {
+        (**self).deep_clear();
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T: DeepClear> DeepClear for Vec<T> {
+    #[inline]
+    fn deep_clear(&mut self) {
+        self[..].deep_clear();
+        clear_spare_capacity(self);
+    }
+}
+
+/// Overwrites the spare capacity of a `Vec`, that is, the memory after
+/// its last element, with zeros.
+#[cfg(feature = "alloc")]
+#[inline]
+pub(crate) fn clear_spare_capacity<T>(vec: &mut Vec<T>) {
+    let len = vec.len();
+    let capacity = vec.capacity();
+    unsafe {
+        let ptr = vec.as_mut_ptr();
+        ptr::write_bytes(ptr.add(len), 0, capacity - len);
+        hide_mem_impl::<[T]>(ptr::slice_from_raw_parts_mut(ptr, capacity));
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl DeepClear for String {
+    #[inline]
+    fn deep_clear(&mut self) {
+        // All-zeros is valid UTF-8.
+        unsafe { self.as_mut_vec() }.deep_clear();
+    }
+}
+
 /// Unsafe trait to indicate which types are safe to set to all-bits-zero.
+///
+/// With the `derive` feature, this trait can be derived for structs
+/// whose fields are all `ZeroSafe`, and for `#[repr]` enums which have
+/// a variant with a zero discriminant.
 pub unsafe trait ZeroSafe {}
 
 // Yes, this is core::nonzero::Zeroable
@@ -121,24 +394,181 @@ unsafe impl ZeroSafe for i32 {}
 unsafe impl ZeroSafe for u32 {}
 unsafe impl ZeroSafe for i64 {}
 unsafe impl ZeroSafe for u64 {}
-#[cfg(feature = "nightly")]
 unsafe impl ZeroSafe for i128 {}
-#[cfg(feature = "nightly")]
 unsafe impl ZeroSafe for u128 {}
+unsafe impl ZeroSafe for f32 {}
+unsafe impl ZeroSafe for f64 {}
+unsafe impl ZeroSafe for bool {}
+unsafe impl ZeroSafe for char {}
+unsafe impl ZeroSafe for () {}
+unsafe impl<T: ?Sized> ZeroSafe for PhantomData<T> {}
+unsafe impl<T: ZeroSafe> ZeroSafe for Wrapping<T> {}
 
-macro_rules! array_impl_zerosafe {
-    ($($N:expr)+) => {
-        $(
-            unsafe impl<T: ZeroSafe> ZeroSafe for [T; $N] {}
-        )+
+// All-bits-zero is `None` for these, thanks to the null pointer optimization.
+unsafe impl<T> ZeroSafe for Option<&T> {}
+unsafe impl<T> ZeroSafe for Option<&mut T> {}
+unsafe impl<T> ZeroSafe for Option<NonNull<T>> {}
+unsafe impl ZeroSafe for Option<NonZeroIsize> {}
+unsafe impl ZeroSafe for Option<NonZeroUsize> {}
+unsafe impl ZeroSafe for Option<NonZeroI8> {}
+unsafe impl ZeroSafe for Option<NonZeroU8> {}
+unsafe impl ZeroSafe for Option<NonZeroI16> {}
+unsafe impl ZeroSafe for Option<NonZeroU16> {}
+unsafe impl ZeroSafe for Option<NonZeroI32> {}
+unsafe impl ZeroSafe for Option<NonZeroU32> {}
+unsafe impl ZeroSafe for Option<NonZeroI64> {}
+unsafe impl ZeroSafe for Option<NonZeroU64> {}
+unsafe impl ZeroSafe for Option<NonZeroI128> {}
+unsafe impl ZeroSafe for Option<NonZeroU128> {}
+
+unsafe impl<T: ZeroSafe, const N: usize> ZeroSafe for [T; N] {}
+
+macro_rules! tuple_impl_zerosafe {
+    ($($T:ident)+) => {
+        unsafe impl<$($T: ZeroSafe),+> ZeroSafe for ($($T,)+) {}
     }
 }
 
-// Implement for fixed-size arrays of ZeroSafe up to 64
-array_impl_zerosafe!{
-     0  1  2  3  4  5  6  7  8  9 10 11 12 13 14 15
-    16 17 18 19 20 21 22 23 24 25 26 27 28 29 30 31
-    32 33 34 35 36 37 38 39 40 41 42 43 44 45 46 47
-    48 49 50 51 52 53 54 55 56 57 58 59 60 61 62 63
-    64
+tuple_impl_zerosafe!(A);
+tuple_impl_zerosafe!(A B);
+tuple_impl_zerosafe!(A B C);
+tuple_impl_zerosafe!(A B C D);
+tuple_impl_zerosafe!(A B C D E);
+tuple_impl_zerosafe!(A B C D E F);
+tuple_impl_zerosafe!(A B C D E F G);
+tuple_impl_zerosafe!(A B C D E F G H);
+tuple_impl_zerosafe!(A B C D E F G H I);
+tuple_impl_zerosafe!(A B C D E F G H I J);
+tuple_impl_zerosafe!(A B C D E F G H I J K);
+tuple_impl_zerosafe!(A B C D E F G H I J K L);
+
+#[cfg(test)]
+mod tests {
+    use super::{Clear, ClearElements, DeepClear, InitializableFromZeroed};
+
+    use core::marker::PhantomData;
+    use core::num::{NonZeroU32, Wrapping};
+    use core::ptr;
+    use core::ptr::NonNull;
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    struct Rounds(u32);
+
+    impl InitializableFromZeroed for Rounds {
+        unsafe fn initialize(place: *mut Self) {
+            ptr::write(place, Rounds(14));
+        }
+    }
+
+    #[test]
+    fn large_arrays() {
+        let mut place = [0x41u8; 48];
+        place[..].clear();
+        assert_eq!(&place[..], &[0; 48][..]);
+
+        let mut place = [[0x0123456789abcdefu64; 128]; 2];
+        place[..].clear();
+        assert!(place.iter().all(|a| a.iter().all(|&x| x == 0)));
+    }
+
+    #[test]
+    fn clear_elements() {
+        let mut place = [Rounds(10); 40];
+        place.clear_elements();
+        assert_eq!(&place[..], &[Rounds(14); 40][..]);
+
+        let mut place = [Some(Rounds(10)), None];
+        place[..].clear_elements();
+        assert_eq!(place, [None, None]);
+    }
+
+    #[test]
+    fn slice_of_floats() {
+        let mut place = [1.5f32, -2.0, 3.25];
+        place[..].clear();
+        assert_eq!(place, [0.0; 3]);
+    }
+
+    #[test]
+    fn slice_of_tuples() {
+        let mut place = [(1u8, 'a', true), (2, 'b', false)];
+        place[..].clear();
+        assert_eq!(place, [(0, '\0', false); 2]);
+    }
+
+    #[test]
+    fn slice_of_wrapping() {
+        let mut place = [Wrapping(0x0123456789abcdefu128); 2];
+        place[..].clear();
+        assert_eq!(place, [Wrapping(0); 2]);
+    }
+
+    #[test]
+    fn slice_of_nonzero_options() {
+        let mut place = [NonZeroU32::new(1), NonZeroU32::new(2)];
+        place[..].clear();
+        assert_eq!(place, [None; 2]);
+    }
+
+    #[test]
+    fn slice_of_pointer_options() {
+        let mut value = 0x41u8;
+        let mut place = [Some(&value), None];
+        place[..].clear();
+        assert_eq!(place, [None; 2]);
+
+        let mut place = [NonNull::new(&mut value as *mut u8); 2];
+        place[..].clear();
+        assert_eq!(place, [None; 2]);
+    }
+
+    #[test]
+    fn deep_clear_tuple() {
+        let mut value = 0x41u8;
+        let mut place = (
+            &mut value as *mut u8,
+            NonZeroU32::new(1),
+            Wrapping(0x41u64),
+            PhantomData::<u8>,
+        );
+        place.deep_clear();
+        assert!(place.0.is_null());
+        assert_eq!(place.1, None);
+        assert_eq!(place.2, Wrapping(0));
+    }
+
+    #[cfg(feature = "alloc")]
+    fn capacity_is_zero<T>(v: &Vec<T>) -> bool {
+        let size = v.capacity() * ::core::mem::size_of::<T>();
+        let bytes = unsafe { ::core::slice::from_raw_parts(v.as_ptr() as *const u8, size) };
+        bytes.iter().all(|&b| b == 0)
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn deep_clear_vec() {
+        let mut place = vec![0x41u8; 64];
+        place.truncate(16);
+        place.deep_clear();
+        assert_eq!(place, [0; 16]);
+        assert!(capacity_is_zero(&place));
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn deep_clear_string() {
+        let mut place = String::with_capacity(32);
+        place.push_str("password");
+        place.deep_clear();
+        assert_eq!(place, "\x00".repeat(8));
+        assert!(capacity_is_zero(unsafe { place.as_mut_vec() }));
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn deep_clear_nested() {
+        let mut place = Some(Box::new(vec![String::from("secret")]));
+        place.deep_clear();
+        assert_eq!(place.unwrap()[0], "\x00".repeat(6));
+    }
 }
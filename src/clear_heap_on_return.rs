@@ -0,0 +1,333 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::cell::Cell;
+use core::cmp;
+use core::mem;
+use core::ptr;
+
+use clear_stack_on_return::clear_stack_on_return;
+use clearing_allocator::clear_block;
+use fnoption::FnOption;
+
+std::thread_local! {
+    // Read by the allocator, so its initialization must not allocate;
+    // see the documentation of `ScopedClearingAllocator`. A const
+    // initializer would need Rust 1.59.
+    #[allow(clippy::missing_const_for_thread_local)]
+    static DEPTH: Cell<usize> = Cell::new(0);
+}
+
+/// Returns whether the current thread is inside a call to
+/// `clear_heap_and_stack_on_return`.
+#[inline]
+fn is_tracking() -> bool {
+    DEPTH.try_with(|depth| depth.get() > 0).unwrap_or(false)
+}
+
+/// Calls a closure and overwrites its stack on return, and makes every
+/// heap allocation it makes be cleared when freed.
+///
+/// This function is a variant of `clear_stack_on_return` which also
+/// tells a `ScopedClearingAllocator`, which must be the global
+/// allocator, to mark every block of memory allocated by the current
+/// thread while the closure runs. Marked blocks are cleared when freed,
+/// even if that happens after this function returns, or on another
+/// thread. Blocks freed or reallocated while the closure runs are also
+/// cleared, even if they were allocated before it.
+///
+/// With any other global allocator, only the stack is cleared.
+///
+/// # Example
+///
+/// ```
+/// # use clear_on_drop::clear_heap_and_stack_on_return;
+/// # fn encrypt(input: &[u8]) -> Vec<u8> { input.to_owned() }
+/// let input = b"abc";
+/// let result = clear_heap_and_stack_on_return(1, || encrypt(input));
+/// ```
+#[inline]
+pub fn clear_heap_and_stack_on_return<F, R>(pages: usize, f: F) -> R
+where
+    F: FnMut() -> R,
+{
+    let _scope = TrackingScope::enter();
+    clear_stack_on_return(pages, f)
+}
+
+/// Calls a closure and overwrites its stack on return, and makes every
+/// heap allocation it makes be cleared when freed.
+///
+/// This function is a variant of `clear_heap_and_stack_on_return` which
+/// also accepts `FnOnce`, at the cost of being slightly slower.
+///
+/// # Example
+///
+/// ```
+/// # use clear_on_drop::clear_heap_and_stack_on_return_fnonce;
+/// # fn encrypt(input: Vec<u8>) -> Vec<u8> { input }
+/// let input = vec![97, 98, 99];
+/// let result = clear_heap_and_stack_on_return_fnonce(1, || encrypt(input));
+/// ```
+#[inline]
+pub fn clear_heap_and_stack_on_return_fnonce<F, R>(pages: usize, f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    let mut f = FnOption::new(f);
+    clear_heap_and_stack_on_return(pages, || f.call_mut()).unwrap()
+}
+
+struct TrackingScope;
+
+impl TrackingScope {
+    #[inline]
+    fn enter() -> Self {
+        DEPTH.with(|depth| depth.set(depth.get() + 1));
+        TrackingScope
+    }
+}
+
+impl Drop for TrackingScope {
+    #[inline]
+    fn drop(&mut self) {
+        DEPTH.with(|depth| depth.set(depth.get() - 1));
+    }
+}
+
+/// A global allocator which clears the blocks of memory allocated
+/// within `clear_heap_and_stack_on_return`.
+///
+/// Each block is preceded by a small header, which records whether it
+/// was allocated within `clear_heap_and_stack_on_return`; only those
+/// blocks are cleared when freed, so unlike a `ClearingAllocator`, the
+/// rest of the program is barely slowed down. A block allocated outside
+/// of it, and freed or reallocated outside of it too, is never cleared,
+/// including the old block left behind when reallocating moves it.
+///
+/// Whether the current thread is within `clear_heap_and_stack_on_return`
+/// is kept in a `thread_local!`, which this allocator reads. Only
+/// targets where the standard library implements it with native
+/// thread-local storage, such as Linux, macOS and Windows, are
+/// supported: elsewhere, its first use on each thread allocates, which
+/// would call back into the allocator before it is initialized.
+///
+/// # Example
+///
+/// ```
+/// use std::alloc::System;
+/// use clear_on_drop::{clear_heap_and_stack_on_return, ScopedClearingAllocator};
+///
+/// #[global_allocator]
+/// static GLOBAL: ScopedClearingAllocator<System> = ScopedClearingAllocator::new(System);
+///
+/// fn main() {
+///     let key = clear_heap_and_stack_on_return(1, || vec![0x41u8; 32]);
+///     // ...
+///     drop(key); // Cleared by the allocator.
+/// }
+/// ```
+#[derive(Debug, Default)]
+pub struct ScopedClearingAllocator<A> {
+    inner: A,
+}
+
+impl<A> ScopedClearingAllocator<A> {
+    /// Creates a new `ScopedClearingAllocator` which allocates from
+    /// `inner`.
+    #[inline]
+    pub const fn new(inner: A) -> Self {
+        ScopedClearingAllocator { inner }
+    }
+
+    /// Returns the wrapped allocator.
+    #[inline]
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+}
+
+const MARK_SIZE: usize = mem::size_of::<usize>();
+
+/// Returns the layout of a block with its header, and the offset of
+/// the block within it.
+#[inline]
+fn outer_layout(layout: Layout) -> Option<(Layout, usize)> {
+    let offset = cmp::max(layout.align(), MARK_SIZE);
+    let size = layout.size().checked_add(offset)?;
+    let align = cmp::max(layout.align(), mem::align_of::<usize>());
+    let outer = Layout::from_size_align(size, align).ok()?;
+    Some((outer, offset))
+}
+
+// The mark is stored in the last word of the header.
+
+#[inline]
+unsafe fn is_marked(ptr: *mut u8) -> bool {
+    *(ptr.sub(MARK_SIZE) as *const usize) != 0
+}
+
+#[inline]
+unsafe fn set_mark(ptr: *mut u8, marked: bool) {
+    *(ptr.sub(MARK_SIZE) as *mut usize) = marked as usize;
+}
+
+impl<A: GlobalAlloc> ScopedClearingAllocator<A> {
+    #[inline]
+    unsafe fn alloc_with(&self, layout: Layout, zeroed: bool, marked: bool) -> *mut u8 {
+        let (outer, offset) = match outer_layout(layout) {
+            Some(outer) => outer,
+            None => return ptr::null_mut(),
+        };
+        let base = if zeroed {
+            self.inner.alloc_zeroed(outer)
+        } else {
+            self.inner.alloc(outer)
+        };
+        if base.is_null() {
+            return base;
+        }
+        let ptr = base.add(offset);
+        set_mark(ptr, marked);
+        ptr
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for ScopedClearingAllocator<A> {
+    #[inline]
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.alloc_with(layout, false, is_tracking())
+    }
+
+    #[inline]
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        self.alloc_with(layout, true, is_tracking())
+    }
+
+    #[inline]
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let (outer, offset) = outer_layout(layout).unwrap();
+        if is_marked(ptr) || is_tracking() {
+            clear_block(ptr, layout.size());
+        }
+        self.inner.dealloc(ptr.sub(offset), outer)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        if is_marked(ptr) || is_tracking() {
+            // Move to a new block, so the old one can be cleared.
+            let new_ptr = self.alloc_with(new_layout, false, true);
+            if !new_ptr.is_null() {
+                ptr::copy_nonoverlapping(ptr, new_ptr, cmp::min(layout.size(), new_size));
+                self.dealloc(ptr, layout);
+            }
+            new_ptr
+        } else {
+            let (outer, offset) = outer_layout(layout).unwrap();
+            let new_outer = match outer_layout(new_layout) {
+                Some((new_outer, _)) => new_outer,
+                None => return ptr::null_mut(),
+            };
+            let base = self.inner.realloc(ptr.sub(offset), outer, new_outer.size());
+            if base.is_null() {
+                return base;
+            }
+            base.add(offset)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{clear_heap_and_stack_on_return, is_tracking, ScopedClearingAllocator};
+
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::slice;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // Counts the blocks which were not cleared before being freed.
+    #[derive(Default)]
+    struct Checking {
+        dirty: AtomicUsize,
+    }
+
+    unsafe impl GlobalAlloc for Checking {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            // Skip the header.
+            let data = slice::from_raw_parts(ptr, layout.size());
+            if data[layout.align().max(8)..].iter().any(|&b| b != 0) {
+                self.dirty.fetch_add(1, Ordering::Relaxed);
+            }
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    unsafe fn fill<A: GlobalAlloc>(allocator: &A, layout: Layout) -> *mut u8 {
+        let ptr = allocator.alloc(layout);
+        assert!(!ptr.is_null());
+        assert_eq!(ptr as usize % layout.align(), 0);
+        ptr.write_bytes(0x41, layout.size());
+        ptr
+    }
+
+    #[test]
+    fn tracking_scope() {
+        assert!(!is_tracking());
+        clear_heap_and_stack_on_return(1, || {
+            assert!(is_tracking());
+            clear_heap_and_stack_on_return(1, || assert!(is_tracking()));
+            assert!(is_tracking());
+        });
+        assert!(!is_tracking());
+    }
+
+    #[test]
+    fn clears_marked_blocks() {
+        let allocator = ScopedClearingAllocator::new(Checking::default());
+        let layout = Layout::from_size_align(100, 32).unwrap();
+        unsafe {
+            let outside = fill(&allocator, layout);
+            let inside = clear_heap_and_stack_on_return(1, || fill(&allocator, layout));
+            allocator.dealloc(inside, layout);
+            assert_eq!(allocator.inner().dirty.load(Ordering::Relaxed), 0);
+            allocator.dealloc(outside, layout);
+            assert_eq!(allocator.inner().dirty.load(Ordering::Relaxed), 1);
+        }
+    }
+
+    #[test]
+    fn clears_blocks_freed_inside() {
+        let allocator = ScopedClearingAllocator::new(Checking::default());
+        let layout = Layout::from_size_align(100, 8).unwrap();
+        unsafe {
+            let outside = fill(&allocator, layout);
+            clear_heap_and_stack_on_return(1, || allocator.dealloc(outside, layout));
+        }
+        assert_eq!(allocator.inner().dirty.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn realloc() {
+        let allocator = ScopedClearingAllocator::new(Checking::default());
+        let layout = Layout::from_size_align(16, 4).unwrap();
+        let new_layout = Layout::from_size_align(1000, 4).unwrap();
+        unsafe {
+            let ptr = clear_heap_and_stack_on_return(1, || fill(&allocator, layout));
+            let ptr = allocator.realloc(ptr, layout, 1000);
+            assert!(slice::from_raw_parts(ptr, 16).iter().all(|&b| b == 0x41));
+            allocator.dealloc(ptr, new_layout);
+            assert_eq!(allocator.inner().dirty.load(Ordering::Relaxed), 0);
+
+            // Outside, the inner allocator moves the block, and frees the
+            // old one without clearing it.
+            let ptr = fill(&allocator, layout);
+            let ptr = allocator.realloc(ptr, layout, 1000);
+            assert!(slice::from_raw_parts(ptr, 16).iter().all(|&b| b == 0x41));
+            allocator.dealloc(ptr, new_layout);
+            assert_eq!(allocator.inner().dirty.load(Ordering::Relaxed), 2);
+        }
+    }
+}
@@ -54,6 +54,9 @@ where
     /// not use `&mut Box<T>` or similar as the place, since the heap
     /// contents won't be cleared in that case. If you need the place
     /// back, use `ClearOnDrop::into_place(...)` instead of a borrow.
+    /// Heap memory owned by the cleared value itself is only wiped if
+    /// its `InitializableFromZeroed` implementation forwards to
+    /// `DeepClear`; use `DeepClearOnDrop` for a `Vec<T>` or `String`.
     #[inline]
     pub fn new(place: P) -> Self {
         ClearOnDrop { _place: place }
@@ -364,6 +367,17 @@ mod tests {
         assert_eq!(place, [0; 4]);
     }
 
+    #[test]
+    fn on_slice_of_large_arrays() {
+        let mut place = [[0u64; 128]; 2];
+        {
+            let mut clear = ClearOnDrop::new(&mut place[..]);
+            clear[1][..4].copy_from_slice(&[0x0123456789abcdef; 4]);
+            assert_eq!(clear[1][3], 0x0123456789abcdef);
+        }
+        assert!(place.iter().all(|a| a.iter().all(|&x| x == 0)));
+    }
+
     #[test]
     fn on_boxed_slice() {
         let place: Box<[u32]> = vec![0; 4].into_boxed_slice();
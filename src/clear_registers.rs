@@ -0,0 +1,387 @@
+/// Overwrites the caller-saved CPU registers with zeros.
+///
+/// Cryptographic code often leaves key material in vector registers,
+/// for instance after using AES-NI, or a vectorized hash function.
+/// Since the callee-saved registers are restored by every function
+/// before returning, only the caller-saved registers can still hold
+/// values computed by a function after it returns; this function
+/// overwrites them.
+///
+/// On x86_64, this clears the general-purpose registers `rax`, `rcx`,
+/// `rdx`, `rsi`, `rdi` and `r8` to `r11`, the vector registers `xmm0` to
+/// `xmm15` (whole `ymm0` to `ymm15` when the processor supports AVX),
+/// and the 128-byte red zone below the stack pointer, which functions
+/// which do not call others use without moving the stack pointer. The
+/// AVX-512 registers are not cleared.
+///
+/// On AArch64, this clears the general-purpose registers `x0` to `x17`,
+/// and the vector registers `v0` to `v31`.
+///
+/// Elsewhere, or when inline assembly is not available, this does
+/// nothing.
+///
+/// # Example
+///
+/// ```
+/// # use clear_on_drop::clear_registers;
+/// # fn encrypt(input: &[u8]) -> Vec<u8> { input.to_owned() }
+/// let result = encrypt(b"abc");
+/// clear_registers();
+/// ```
+#[inline(always)]
+pub fn clear_registers() {
+    // Always inlined, so the red zone cleared is the caller's.
+    impls::clear_registers();
+}
+
+/// Returns whether `clear_registers` clears the CPU registers, which
+/// depends on the target architecture, and on inline assembly being
+/// available when building this crate.
+#[inline]
+pub fn clears_registers() -> bool {
+    cfg!(all(
+        clear_on_drop_asm,
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    ))
+}
+
+// On x86_64, the registers are cleared with SSE or AVX instructions,
+// depending on what the processor supports, and the red zone with the
+// zeroed `xmm0`.
+#[cfg(all(clear_on_drop_asm, target_arch = "x86_64"))]
+mod impls {
+    use core::arch::asm;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    #[inline(always)]
+    pub fn clear_registers() {
+        unsafe {
+            if has_avx() {
+                asm!(
+                    "vzeroall",
+                    // Callee-saved on Windows.
+                    out("xmm6") _,
+                    out("xmm7") _,
+                    out("xmm8") _,
+                    out("xmm9") _,
+                    out("xmm10") _,
+                    out("xmm11") _,
+                    out("xmm12") _,
+                    out("xmm13") _,
+                    out("xmm14") _,
+                    out("xmm15") _,
+                    clobber_abi("C"),
+                    options(nostack, nomem, preserves_flags),
+                );
+            } else {
+                asm!(
+                    "xorps xmm0, xmm0",
+                    "xorps xmm1, xmm1",
+                    "xorps xmm2, xmm2",
+                    "xorps xmm3, xmm3",
+                    "xorps xmm4, xmm4",
+                    "xorps xmm5, xmm5",
+                    "xorps xmm6, xmm6",
+                    "xorps xmm7, xmm7",
+                    "xorps xmm8, xmm8",
+                    "xorps xmm9, xmm9",
+                    "xorps xmm10, xmm10",
+                    "xorps xmm11, xmm11",
+                    "xorps xmm12, xmm12",
+                    "xorps xmm13, xmm13",
+                    "xorps xmm14, xmm14",
+                    "xorps xmm15, xmm15",
+                    out("xmm6") _,
+                    out("xmm7") _,
+                    out("xmm8") _,
+                    out("xmm9") _,
+                    out("xmm10") _,
+                    out("xmm11") _,
+                    out("xmm12") _,
+                    out("xmm13") _,
+                    out("xmm14") _,
+                    out("xmm15") _,
+                    clobber_abi("C"),
+                    options(nostack, nomem, preserves_flags),
+                );
+            }
+            // Without `nostack`, the compiler keeps nothing in the red zone.
+            asm!(
+                "xorps xmm0, xmm0",
+                "movups [rsp - 16], xmm0",
+                "movups [rsp - 32], xmm0",
+                "movups [rsp - 48], xmm0",
+                "movups [rsp - 64], xmm0",
+                "movups [rsp - 80], xmm0",
+                "movups [rsp - 96], xmm0",
+                "movups [rsp - 112], xmm0",
+                "movups [rsp - 128], xmm0",
+                "xor eax, eax",
+                "xor ecx, ecx",
+                "xor edx, edx",
+                "xor esi, esi",
+                "xor edi, edi",
+                "xor r8d, r8d",
+                "xor r9d, r9d",
+                "xor r10d, r10d",
+                "xor r11d, r11d",
+                // Callee-saved on Windows.
+                out("rsi") _,
+                out("rdi") _,
+                clobber_abi("C"),
+            );
+        }
+    }
+
+    /// Returns whether the processor and the operating system support AVX,
+    /// so `vzeroall` can be used.
+    #[inline]
+    fn has_avx() -> bool {
+        // 0 when not checked yet, 1 without AVX, 2 with AVX.
+        static AVX: AtomicUsize = AtomicUsize::new(0);
+
+        if cfg!(target_feature = "avx") {
+            return true;
+        }
+        let mut avx = AVX.load(Ordering::Relaxed);
+        if avx == 0 {
+            avx = if detect_avx() { 2 } else { 1 };
+            AVX.store(avx, Ordering::Relaxed);
+        }
+        avx == 2
+    }
+
+    #[cold]
+    fn detect_avx() -> bool {
+        use core::arch::x86_64::__cpuid;
+
+        const OSXSAVE: u32 = 1 << 27;
+        const AVX: u32 = 1 << 28;
+        // The SSE and AVX state are enabled in XCR0.
+        const XCR0_YMM: u32 = 0b110;
+
+        // Safe in newer versions of Rust.
+        #[allow(unused_unsafe)]
+        let ecx = unsafe { __cpuid(1) }.ecx;
+        if ecx & (OSXSAVE | AVX) != OSXSAVE | AVX {
+            return false;
+        }
+        let xcr0: u32;
+        unsafe {
+            asm!(
+                "xgetbv",
+                in("ecx") 0,
+                out("eax") xcr0,
+                out("edx") _,
+                options(nostack, nomem, preserves_flags),
+            );
+        }
+        xcr0 & XCR0_YMM == XCR0_YMM
+    }
+}
+
+// On AArch64, there is no red zone, so only the registers are cleared.
+#[cfg(all(clear_on_drop_asm, target_arch = "aarch64"))]
+mod impls {
+    use core::arch::asm;
+
+    #[inline(always)]
+    pub fn clear_registers() {
+        unsafe {
+            asm!(
+                "movi v0.2d, #0",
+                "movi v1.2d, #0",
+                "movi v2.2d, #0",
+                "movi v3.2d, #0",
+                "movi v4.2d, #0",
+                "movi v5.2d, #0",
+                "movi v6.2d, #0",
+                "movi v7.2d, #0",
+                "movi v8.2d, #0",
+                "movi v9.2d, #0",
+                "movi v10.2d, #0",
+                "movi v11.2d, #0",
+                "movi v12.2d, #0",
+                "movi v13.2d, #0",
+                "movi v14.2d, #0",
+                "movi v15.2d, #0",
+                "movi v16.2d, #0",
+                "movi v17.2d, #0",
+                "movi v18.2d, #0",
+                "movi v19.2d, #0",
+                "movi v20.2d, #0",
+                "movi v21.2d, #0",
+                "movi v22.2d, #0",
+                "movi v23.2d, #0",
+                "movi v24.2d, #0",
+                "movi v25.2d, #0",
+                "movi v26.2d, #0",
+                "movi v27.2d, #0",
+                "movi v28.2d, #0",
+                "movi v29.2d, #0",
+                "movi v30.2d, #0",
+                "movi v31.2d, #0",
+                "mov x0, xzr",
+                "mov x1, xzr",
+                "mov x2, xzr",
+                "mov x3, xzr",
+                "mov x4, xzr",
+                "mov x5, xzr",
+                "mov x6, xzr",
+                "mov x7, xzr",
+                "mov x8, xzr",
+                "mov x9, xzr",
+                "mov x10, xzr",
+                "mov x11, xzr",
+                "mov x12, xzr",
+                "mov x13, xzr",
+                "mov x14, xzr",
+                "mov x15, xzr",
+                "mov x16, xzr",
+                "mov x17, xzr",
+                // Only the high halves of v8 to v15 are caller-saved, so
+                // the compiler restores the low halves.
+                clobber_abi("C"),
+                options(nostack, nomem, preserves_flags),
+            );
+        }
+    }
+}
+
+// Elsewhere, or without inline assembly, nothing is cleared.
+#[cfg(not(all(
+    clear_on_drop_asm,
+    any(target_arch = "x86_64", target_arch = "aarch64")
+)))]
+mod impls {
+    #[inline(always)]
+    pub fn clear_registers() {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::clear_registers;
+
+    #[test]
+    fn clear_registers_runs() {
+        // Also after the result of detecting AVX is cached.
+        clear_registers();
+        clear_registers();
+    }
+
+    #[cfg(all(clear_on_drop_asm, target_arch = "x86_64"))]
+    #[test]
+    fn clears_x86_64_registers() {
+        use core::arch::asm;
+
+        extern "C" fn clear() {
+            clear_registers();
+        }
+
+        // Not rax, which the prologue of `clear` may push to align the
+        // stack, and its epilogue pop again.
+        let mut gprs = [0u64; 8];
+        let mut xmms = [0u64; 32];
+        unsafe {
+            asm!(
+                "mov rdx, rcx",
+                "mov rsi, rcx",
+                "mov rdi, rcx",
+                "mov r8, rcx",
+                "mov r9, rcx",
+                "mov r10, rcx",
+                "mov r11, rcx",
+                "movq xmm0, rcx",
+                "punpcklqdq xmm0, xmm0",
+                "movaps xmm1, xmm0",
+                "movaps xmm2, xmm0",
+                "movaps xmm3, xmm0",
+                "movaps xmm4, xmm0",
+                "movaps xmm5, xmm0",
+                "movaps xmm6, xmm0",
+                "movaps xmm7, xmm0",
+                "movaps xmm8, xmm0",
+                "movaps xmm9, xmm0",
+                "movaps xmm10, xmm0",
+                "movaps xmm11, xmm0",
+                "movaps xmm12, xmm0",
+                "movaps xmm13, xmm0",
+                "movaps xmm14, xmm0",
+                "movaps xmm15, xmm0",
+                "call r12",
+                "mov [r13], rcx",
+                "mov [r13 + 8], rdx",
+                "mov [r13 + 16], rsi",
+                "mov [r13 + 24], rdi",
+                "mov [r13 + 32], r8",
+                "mov [r13 + 40], r9",
+                "mov [r13 + 48], r10",
+                "mov [r13 + 56], r11",
+                "movups [r14], xmm0",
+                "movups [r14 + 16], xmm1",
+                "movups [r14 + 32], xmm2",
+                "movups [r14 + 48], xmm3",
+                "movups [r14 + 64], xmm4",
+                "movups [r14 + 80], xmm5",
+                "movups [r14 + 96], xmm6",
+                "movups [r14 + 112], xmm7",
+                "movups [r14 + 128], xmm8",
+                "movups [r14 + 144], xmm9",
+                "movups [r14 + 160], xmm10",
+                "movups [r14 + 176], xmm11",
+                "movups [r14 + 192], xmm12",
+                "movups [r14 + 208], xmm13",
+                "movups [r14 + 224], xmm14",
+                "movups [r14 + 240], xmm15",
+                in("rcx") 0x4141_4141_4141_4141u64,
+                in("r12") clear as extern "C" fn(),
+                in("r13") gprs.as_mut_ptr(),
+                in("r14") xmms.as_mut_ptr(),
+                out("xmm6") _,
+                out("xmm7") _,
+                out("xmm8") _,
+                out("xmm9") _,
+                out("xmm10") _,
+                out("xmm11") _,
+                out("xmm12") _,
+                out("xmm13") _,
+                out("xmm14") _,
+                out("xmm15") _,
+                clobber_abi("C"),
+            );
+        }
+        assert_eq!(gprs, [0; 8]);
+        assert_eq!(xmms, [0; 32]);
+    }
+
+    #[cfg(all(clear_on_drop_asm, target_arch = "x86_64"))]
+    #[test]
+    fn clears_x86_64_red_zone() {
+        use core::arch::asm;
+
+        let mut red_zone = [0x41u8; 128];
+        unsafe {
+            asm!(
+                "lea rdi, [rsp - 128]",
+                "mov ecx, 128",
+                "mov al, 0x41",
+                "rep stosb",
+                out("rax") _,
+                out("rcx") _,
+                out("rdi") _,
+            );
+        }
+        clear_registers();
+        unsafe {
+            asm!(
+                "lea rsi, [rsp - 128]",
+                "mov ecx, 128",
+                "rep movsb",
+                inout("rdi") red_zone.as_mut_ptr() => _,
+                out("rcx") _,
+                out("rsi") _,
+            );
+        }
+        assert_eq!(red_zone[..], [0; 128][..]);
+    }
+}
@@ -1,12 +1,28 @@
+#[cfg(all(
+    clear_on_drop_asm,
+    not(clear_on_drop_hide = "volatile"),
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+use core::arch::asm;
+use core::cmp;
+use core::fmt;
+use core::mem::MaybeUninit;
+#[cfg(unix)]
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use clear_registers::clear_registers;
 use fnoption::FnOption;
-use hide::{hide_mem, hide_ptr};
+use hide::{clear_mem, hide_mem, hide_ptr};
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use sys;
 
 /// Calls a closure and overwrites its stack on return.
 ///
 /// This function calls `clear_stack` after calling the passed closure,
 /// taking care to prevent either of them being inlined, so the stack
 /// used by the closure will be overwritten with zeros (as long as a
-/// large enough number of `pages` is used).
+/// large enough number of `pages` is used). Each page is `page_size()`
+/// bytes.
 ///
 /// For technical reasons, this function can be used only with `Fn` or
 /// `FnMut`. If all you have is a `FnOnce`, use the auxiliary function
@@ -21,13 +37,183 @@ use hide::{hide_mem, hide_ptr};
 /// let result = clear_stack_on_return(1, || encrypt(input));
 /// ```
 #[inline]
-pub fn clear_stack_on_return<F, R>(pages: usize, mut f: F) -> R
+pub fn clear_stack_on_return<F, R>(pages: usize, f: F) -> R
+where
+    F: FnMut() -> R,
+{
+    clear_stack_on_return_bytes(pages.saturating_mul(page_size()), f)
+}
+
+/// Calls a closure and overwrites exactly `bytes` bytes of its stack on
+/// return.
+///
+/// This function is a variant of `clear_stack_on_return` for callers
+/// which know how much stack the closure needs, for instance on small
+/// thread stacks, where a whole page might be too much.
+///
+/// # Example
+///
+/// ```
+/// # use clear_on_drop::clear_stack_on_return_bytes;
+/// # fn encrypt(input: &[u8]) -> Vec<u8> { input.to_owned() }
+/// let input = b"abc";
+/// let result = clear_stack_on_return_bytes(512, || encrypt(input));
+/// ```
+#[inline]
+pub fn clear_stack_on_return_bytes<F, R>(bytes: usize, mut f: F) -> R
 where
     F: FnMut() -> R,
 {
-    let _clear = ClearStackOnDrop { pages: pages };
+    let _clear = ClearStackOnDrop {
+        bytes,
+        registers: false,
+    };
     // Do not inline f to make sure clear_stack uses the same stack space.
-    hide_ptr::<&mut FnMut() -> R>(&mut f)()
+    hide_ptr::<&mut dyn FnMut() -> R>(&mut f)()
+}
+
+/// Options for `clear_stack_on_return_with_options`.
+///
+/// # Example
+///
+/// ```
+/// # use clear_on_drop::{clear_stack_on_return_with_options, ClearStackOptions};
+/// # fn encrypt(input: &[u8]) -> Vec<u8> { input.to_owned() }
+/// let input = b"abc";
+/// let options = ClearStackOptions::new().pages(2).clear_registers(true);
+/// let result = clear_stack_on_return_with_options(options, || encrypt(input));
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClearStackOptions {
+    bytes: usize,
+    registers: bool,
+}
+
+impl ClearStackOptions {
+    /// Creates the default options, which overwrite one page of stack,
+    /// like `clear_stack_on_return(1, f)`, and no registers.
+    #[inline]
+    pub fn new() -> Self {
+        ClearStackOptions {
+            bytes: page_size(),
+            registers: false,
+        }
+    }
+
+    /// Sets how much stack is overwritten, in pages of `page_size()`
+    /// bytes.
+    #[inline]
+    pub fn pages(mut self, pages: usize) -> Self {
+        self.bytes = pages.saturating_mul(page_size());
+        self
+    }
+
+    /// Sets how much stack is overwritten, in bytes.
+    #[inline]
+    pub fn bytes(mut self, bytes: usize) -> Self {
+        self.bytes = bytes;
+        self
+    }
+
+    /// Sets whether the caller-saved CPU registers are also overwritten
+    /// with `clear_registers`, after the stack. Disabled by default.
+    #[inline]
+    pub fn clear_registers(mut self, enable: bool) -> Self {
+        self.registers = enable;
+        self
+    }
+}
+
+impl Default for ClearStackOptions {
+    #[inline]
+    fn default() -> Self {
+        ClearStackOptions::new()
+    }
+}
+
+/// Calls a closure and overwrites its stack on return, with the given
+/// options.
+///
+/// This function is a variant of `clear_stack_on_return` which can also
+/// overwrite the CPU registers the closure might have left secrets in.
+/// See `ClearStackOptions` for the details.
+#[inline]
+pub fn clear_stack_on_return_with_options<F, R>(options: ClearStackOptions, mut f: F) -> R
+where
+    F: FnMut() -> R,
+{
+    let _clear = ClearStackOnDrop {
+        bytes: options.bytes,
+        registers: options.registers,
+    };
+    // Do not inline f to make sure clear_stack uses the same stack space.
+    hide_ptr::<&mut dyn FnMut() -> R>(&mut f)()
+}
+
+/// Calls a closure and overwrites its stack on return, if the stack is
+/// large enough.
+///
+/// This function is a variant of `clear_stack_on_return` which returns
+/// an error, without calling the closure, when `pages` pages would not
+/// fit in what is left of the thread stack (see `available_stack`),
+/// instead of clearing less than requested.
+///
+/// # Example
+///
+/// ```
+/// # use clear_on_drop::try_clear_stack_on_return;
+/// # fn encrypt(input: &[u8]) -> Vec<u8> { input.to_owned() }
+/// let input = b"abc";
+/// match try_clear_stack_on_return(4, || encrypt(input)) {
+///     Ok(result) => {}
+///     Err(err) => eprintln!("cannot encrypt: {}", err),
+/// }
+/// ```
+#[inline]
+pub fn try_clear_stack_on_return<F, R>(pages: usize, f: F) -> Result<R, StackSizeError>
+where
+    F: FnMut() -> R,
+{
+    let requested = pages.saturating_mul(page_size());
+    match available_stack() {
+        Some(available) if available < requested => Err(StackSizeError {
+            requested,
+            available,
+        }),
+        _ => Ok(clear_stack_on_return_bytes(requested, f)),
+    }
+}
+
+/// The error returned by `try_clear_stack_on_return` when the thread
+/// stack is too small to clear as much as requested.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StackSizeError {
+    requested: usize,
+    available: usize,
+}
+
+impl StackSizeError {
+    /// Returns the number of bytes which were to be cleared.
+    #[inline]
+    pub fn requested(&self) -> usize {
+        self.requested
+    }
+
+    /// Returns the number of bytes which could have been cleared.
+    #[inline]
+    pub fn available(&self) -> usize {
+        self.available
+    }
+}
+
+impl fmt::Display for StackSizeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "cannot clear {} bytes of stack, only {} are left",
+            self.requested, self.available
+        )
+    }
 }
 
 /// Calls a closure and overwrites its stack on return.
@@ -53,26 +239,347 @@ where
 }
 
 struct ClearStackOnDrop {
-    pages: usize,
+    bytes: usize,
+    registers: bool,
 }
 
 impl Drop for ClearStackOnDrop {
     #[inline]
     fn drop(&mut self) {
-        // Do not inline clear_stack.
-        hide_ptr::<fn(usize)>(clear_stack)(self.pages);
+        // Do not inline clear_stack_bytes.
+        hide_ptr::<fn(usize)>(clear_stack_bytes)(self.bytes);
+        if self.registers {
+            clear_registers();
+        }
+    }
+}
+
+/// Returns the size of the pages used by `clear_stack` and
+/// `clear_stack_on_return`.
+///
+/// This is the size of a memory page on Unix systems, which can be
+/// larger than 4096 bytes (for instance, 16 KiB or 64 KiB), and 4096
+/// bytes elsewhere.
+#[cfg(unix)]
+pub fn page_size() -> usize {
+    static PAGE_SIZE: AtomicUsize = AtomicUsize::new(0);
+
+    let mut size = PAGE_SIZE.load(Ordering::Relaxed);
+    if size == 0 {
+        size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        PAGE_SIZE.store(size, Ordering::Relaxed);
     }
+    size
+}
+
+/// Returns the size of the pages used by `clear_stack` and
+/// `clear_stack_on_return`.
+///
+/// This is the size of a memory page on Unix systems, which can be
+/// larger than 4096 bytes (for instance, 16 KiB or 64 KiB), and 4096
+/// bytes elsewhere.
+#[cfg(not(unix))]
+#[inline]
+pub fn page_size() -> usize {
+    4096
 }
 
 /// Overwrites a few pages of stack.
 ///
-/// This function will overwrite `pages` 4096-byte blocks of the stack
-/// with zeros.
+/// This function will overwrite `pages` blocks of `page_size()` bytes
+/// of the stack with zeros.
+#[inline]
 pub fn clear_stack(pages: usize) {
-    if pages > 0 {
-        let mut buf = [0u8; 4096];
-        hide_mem(&mut buf); // prevent moving after recursive call
-        clear_stack(pages - 1);
-        hide_mem(&mut buf); // prevent reuse of stack space for call
+    clear_stack_bytes(pages.saturating_mul(page_size()));
+}
+
+/// Overwrites `bytes` bytes of stack.
+///
+/// The stack is overwritten with zeros, starting just below the frame
+/// of the caller. Where the bounds of the thread stack are known, at
+/// most `available_stack()` bytes are overwritten, so a request which
+/// is too large for the thread stack does not overflow it.
+///
+/// When inline assembly is available on x86_64 and AArch64, the stack
+/// is overwritten in a single frame; elsewhere, and with the `volatile`
+/// feature, this works like `clear_stack_bytes_recursive`.
+pub fn clear_stack_bytes(bytes: usize) {
+    let bytes = clamp_to_stack(bytes);
+    #[cfg(all(
+        clear_on_drop_asm,
+        not(clear_on_drop_hide = "volatile"),
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    ))]
+    clear_stack_frame(bytes);
+    #[cfg(not(all(
+        clear_on_drop_asm,
+        not(clear_on_drop_hide = "volatile"),
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    )))]
+    clear_stack_blocks(bytes);
+}
+
+/// Overwrites `bytes` bytes of stack, with one recursive call for each
+/// block of up to 4 KiB.
+///
+/// This is the portable implementation of `clear_stack_bytes`, which is
+/// used when the stack cannot be overwritten in a single frame. It is
+/// slower, since each block costs a call and two `hide_mem` barriers.
+/// It is public only so the benchmarks can compare both.
+#[doc(hidden)]
+pub fn clear_stack_bytes_recursive(bytes: usize) {
+    clear_stack_blocks(clamp_to_stack(bytes));
+}
+
+#[inline]
+fn clamp_to_stack(bytes: usize) -> usize {
+    match available_stack() {
+        Some(available) => cmp::min(bytes, available),
+        None => bytes,
+    }
+}
+
+/// The stack left free below the cleared area, for signal handlers and
+/// the frames of the functions called while clearing.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const STACK_RESERVE: usize = 16 * 1024;
+
+/// Returns how many bytes of stack `clear_stack_bytes` can overwrite
+/// when called from the caller's frame, or `None` if the bounds of the
+/// thread stack are unknown.
+///
+/// On Linux and Android, the bounds of the thread stack are looked up
+/// with `pthread_getattr_np`. Some stack is kept free above its guard
+/// area, so what can be overwritten is a little less than what is left.
+/// Elsewhere, this returns `None`, and `clear_stack_bytes` trusts the
+/// caller to request no more than fits.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+#[inline(never)]
+pub fn available_stack() -> Option<usize> {
+    let (low, high) = sys::stack_bounds()?;
+    // The address of a local is close enough to the stack pointer.
+    let here = &low as *const usize as usize;
+    if here < low || here > high {
+        // Not on the thread stack, for instance in a signal handler
+        // running on an alternate stack.
+        return None;
+    }
+    let left = (here - low).saturating_sub(STACK_RESERVE);
+    // Each block of `clear_stack_blocks` is in a frame which also holds
+    // a return address and saved registers.
+    Some(left - left / 16)
+}
+
+/// Returns how many bytes of stack `clear_stack_bytes` can overwrite
+/// when called from the caller's frame, or `None` if the bounds of the
+/// thread stack are unknown.
+///
+/// On Linux and Android, the bounds of the thread stack are looked up
+/// with `pthread_getattr_np`. Some stack is kept free above its guard
+/// area, so what can be overwritten is a little less than what is left.
+/// Elsewhere, this returns `None`, and `clear_stack_bytes` trusts the
+/// caller to request no more than fits.
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+#[inline]
+pub fn available_stack() -> Option<usize> {
+    None
+}
+
+/// Overwrites exactly `bytes` bytes of stack.
+fn clear_stack_blocks(bytes: usize) {
+    // Large blocks for speed, small blocks to use little more than the
+    // requested space.
+    if bytes >= 4096 {
+        clear_stack_block::<4096>(bytes);
+    } else if bytes > 0 {
+        clear_stack_block::<256>(bytes);
+    }
+}
+
+/// Overwrites up to `N` bytes of stack, and calls `clear_stack_blocks`
+/// for the rest.
+///
+/// Not inlined, since `clear_stack_blocks` would then have a frame
+/// large enough for blocks of both sizes, leaving gaps between the
+/// small blocks.
+#[inline(never)]
+fn clear_stack_block<const N: usize>(bytes: usize) {
+    let mut buf = MaybeUninit::<[u8; N]>::uninit();
+    let len = cmp::min(bytes, N);
+    // Write the zeros at the end nearest to the caller, since the stack
+    // grows down, and prevent moving after recursive call.
+    unsafe { clear_mem((buf.as_mut_ptr() as *mut u8).add(N - len), len) };
+    if bytes > len {
+        clear_stack_blocks(bytes - len);
+    }
+    hide_mem(&mut buf); // prevent reuse of stack space for call
+}
+
+/// Overwrites exactly `bytes` bytes of stack below the stack pointer,
+/// within a single frame.
+///
+/// The stack pointer is moved down one 4 KiB step at a time, touching
+/// each step, like the stack probes emitted by the compiler for large
+/// frames, so the guard page is hit before anything past it is written.
+/// The area is then filled with zeros, and the stack pointer restored.
+#[cfg(all(
+    clear_on_drop_asm,
+    not(clear_on_drop_hide = "volatile"),
+    target_arch = "x86_64"
+))]
+#[inline(never)]
+fn clear_stack_frame(bytes: usize) {
+    // Keep the stack pointer aligned.
+    let len = bytes.saturating_add(15) & !15;
+    unsafe {
+        asm!(
+            "mov {saved}, rsp",
+            "mov {left}, rcx",
+            "2:",
+            "cmp {left}, 4096",
+            "jbe 3f",
+            "sub rsp, 4096",
+            "mov qword ptr [rsp], 0",
+            "sub {left}, 4096",
+            "jmp 2b",
+            "3:",
+            "sub rsp, {left}",
+            "mov rdi, rsp",
+            "rep stosb",
+            "mov rsp, {saved}",
+            saved = out(reg) _,
+            left = out(reg) _,
+            inout("rcx") len => _,
+            out("rdi") _,
+            in("rax") 0usize,
+        );
+    }
+}
+
+/// Overwrites exactly `bytes` bytes of stack below the stack pointer,
+/// within a single frame.
+///
+/// The stack pointer is moved down one 4 KiB step at a time, touching
+/// each step, like the stack probes emitted by the compiler for large
+/// frames, so the guard page is hit before anything past it is written.
+/// The area is then filled with zeros, and the stack pointer restored.
+#[cfg(all(
+    clear_on_drop_asm,
+    not(clear_on_drop_hide = "volatile"),
+    target_arch = "aarch64"
+))]
+#[inline(never)]
+fn clear_stack_frame(bytes: usize) {
+    // Keep the stack pointer aligned.
+    let len = bytes.saturating_add(15) & !15;
+    unsafe {
+        asm!(
+            "mov {saved}, sp",
+            "mov {left}, {len}",
+            "2:",
+            "cmp {left}, #4096",
+            "b.ls 3f",
+            "sub sp, sp, #4096",
+            "str xzr, [sp]",
+            "sub {left}, {left}, #4096",
+            "b 2b",
+            "3:",
+            "sub sp, sp, {left}",
+            "mov {ptr}, sp",
+            "4:",
+            "cbz {len}, 5f",
+            "stp xzr, xzr, [{ptr}], #16",
+            "sub {len}, {len}, #16",
+            "b 4b",
+            "5:",
+            "mov sp, {saved}",
+            saved = out(reg) _,
+            left = out(reg) _,
+            ptr = out(reg) _,
+            len = inout(reg) len => _,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        available_stack, clear_stack_bytes, clear_stack_bytes_recursive, clear_stack_on_return,
+        clear_stack_on_return_with_options, page_size, try_clear_stack_on_return,
+        ClearStackOptions,
+    };
+    use hide::{hide_mem, hide_ptr};
+
+    use core::ptr;
+    use std::thread;
+
+    // Leaves a pattern on the stack, and returns where it was.
+    fn dirty_stack() -> usize {
+        let mut buf = [0x41u8; 8192];
+        hide_mem(&mut buf);
+        &buf as *const _ as usize
+    }
+
+    #[test]
+    fn page_size_is_power_of_two() {
+        assert!(page_size() >= 4096);
+        assert_eq!(page_size() & (page_size() - 1), 0);
+    }
+
+    fn check_clears(clear: fn(usize)) {
+        // Both functions run just below the frame of this function.
+        let buf = hide_ptr::<fn() -> usize>(dirty_stack)();
+        hide_ptr::<fn(usize)>(clear)(8192 + 1024);
+        let data = unsafe { ptr::read_volatile(buf as *const [u8; 8192]) };
+        // Reading calls functions, whose frames could hold a few bytes
+        // equal to the pattern by chance.
+        assert!(!data.windows(16).any(|w| w.iter().all(|&b| b == 0x41)));
+    }
+
+    #[test]
+    fn clears_stack() {
+        check_clears(clear_stack_bytes);
+        check_clears(clear_stack_bytes_recursive);
+    }
+
+    #[test]
+    fn clears_small_sizes() {
+        for &bytes in &[0, 1, 15, 16, 17, 255, 256, 257, 4095, 4096, 4097, 10000] {
+            clear_stack_bytes(bytes);
+            clear_stack_bytes_recursive(bytes);
+        }
+    }
+
+    #[test]
+    fn with_options() {
+        let options = ClearStackOptions::new();
+        assert_eq!(options, ClearStackOptions::new().pages(1));
+        assert_eq!(options.pages(2), options.bytes(2 * page_size()));
+        let options = options.bytes(8192 + 1024).clear_registers(true);
+        assert_eq!(clear_stack_on_return_with_options(options, || 0x41), 0x41);
+
+        let buf = hide_ptr::<fn() -> usize>(dirty_stack)();
+        clear_stack_on_return_with_options(options, || ());
+        let data = unsafe { ptr::read_volatile(buf as *const [u8; 8192]) };
+        assert!(!data.windows(16).any(|w| w.iter().all(|&b| b == 0x41)));
+    }
+
+    #[test]
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn bounded_by_thread_stack() {
+        let small = thread::Builder::new().stack_size(128 * 1024);
+        let handle = small
+            .spawn(|| {
+                let available = available_stack().unwrap();
+                assert!(available < 128 * 1024);
+                // Far more than the thread stack.
+                assert_eq!(clear_stack_on_return(1000, || 0x41), 0x41);
+                let err = try_clear_stack_on_return(1000, || 0x41).unwrap_err();
+                assert_eq!(err.requested(), 1000 * page_size());
+                assert!(err.available() < 128 * 1024);
+                assert_eq!(try_clear_stack_on_return(1, || 0x41), Ok(0x41));
+            })
+            .unwrap();
+        handle.join().unwrap();
     }
 }
@@ -0,0 +1,160 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::cmp;
+use core::ptr;
+
+use hide::clear_mem;
+
+/// A global allocator which clears every block of memory it frees.
+///
+/// `ClearOnDrop` only clears the values it is given, but cannot reach
+/// the temporary buffers allocated and freed deep inside other crates.
+/// This wrapper around another allocator (usually `std::alloc::System`)
+/// overwrites each block with zeros before freeing it, and makes
+/// `realloc` always move to a new block, so the old block can be
+/// cleared too.
+///
+/// Since every freed byte is written, and every `realloc` copies, it
+/// makes allocation-heavy code noticeably slower.
+///
+/// # Example
+///
+/// ```
+/// use std::alloc::System;
+/// use clear_on_drop::ClearingAllocator;
+///
+/// #[global_allocator]
+/// static GLOBAL: ClearingAllocator<System> = ClearingAllocator::new(System);
+///
+/// fn main() {
+///     let key = vec![0x41u8; 32];
+///     // ...
+///     drop(key); // Cleared by the allocator.
+/// }
+/// ```
+#[derive(Debug, Default)]
+pub struct ClearingAllocator<A> {
+    inner: A,
+}
+
+impl<A> ClearingAllocator<A> {
+    /// Creates a new `ClearingAllocator` which allocates from `inner`.
+    #[inline]
+    pub const fn new(inner: A) -> Self {
+        ClearingAllocator { inner }
+    }
+
+    /// Returns the wrapped allocator.
+    #[inline]
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for ClearingAllocator<A> {
+    #[inline]
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.inner.alloc(layout)
+    }
+
+    #[inline]
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        self.inner.alloc_zeroed(layout)
+    }
+
+    #[inline]
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        clear_block(ptr, layout.size());
+        self.inner.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        // Letting the inner allocator resize the block could move it and
+        // free the old block without clearing it.
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        let new_ptr = self.inner.alloc(new_layout);
+        if !new_ptr.is_null() {
+            ptr::copy_nonoverlapping(ptr, new_ptr, cmp::min(layout.size(), new_size));
+            self.dealloc(ptr, layout);
+        }
+        new_ptr
+    }
+}
+
+/// Overwrites `len` bytes at `ptr` with zeros.
+#[inline]
+pub(crate) unsafe fn clear_block(ptr: *mut u8, len: usize) {
+    clear_mem(ptr, len);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ClearingAllocator;
+
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::slice;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // Counts the blocks which were not cleared before being freed.
+    #[derive(Default)]
+    struct Checking {
+        dirty: AtomicUsize,
+    }
+
+    unsafe impl GlobalAlloc for Checking {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            if slice::from_raw_parts(ptr, layout.size())
+                .iter()
+                .any(|&b| b != 0)
+            {
+                self.dirty.fetch_add(1, Ordering::Relaxed);
+            }
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    #[test]
+    fn dealloc() {
+        let allocator = ClearingAllocator::new(Checking::default());
+        let layout = Layout::from_size_align(100, 8).unwrap();
+        unsafe {
+            let ptr = allocator.alloc(layout);
+            ptr.write_bytes(0x41, layout.size());
+            allocator.dealloc(ptr, layout);
+        }
+        assert_eq!(allocator.inner().dirty.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn realloc() {
+        let allocator = ClearingAllocator::new(Checking::default());
+        let layout = Layout::from_size_align(16, 4).unwrap();
+        unsafe {
+            let ptr = allocator.alloc(layout);
+            ptr.write_bytes(0x41, 16);
+            let ptr = allocator.realloc(ptr, layout, 1000);
+            assert!(slice::from_raw_parts(ptr, 16).iter().all(|&b| b == 0x41));
+            let layout = Layout::from_size_align(1000, 4).unwrap();
+            ptr.write_bytes(0x42, 1000);
+            let ptr = allocator.realloc(ptr, layout, 8);
+            assert!(slice::from_raw_parts(ptr, 8).iter().all(|&b| b == 0x42));
+            allocator.dealloc(ptr, Layout::from_size_align(8, 4).unwrap());
+        }
+        assert_eq!(allocator.inner().dirty.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn unchecked_inner() {
+        let allocator = Checking::default();
+        let layout = Layout::from_size_align(100, 8).unwrap();
+        unsafe {
+            let ptr = allocator.alloc(layout);
+            ptr.write_bytes(0x41, layout.size());
+            allocator.dealloc(ptr, layout);
+        }
+        assert_eq!(allocator.dirty.load(Ordering::Relaxed), 1);
+    }
+}
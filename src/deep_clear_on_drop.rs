@@ -0,0 +1,134 @@
+use core::fmt;
+use core::mem;
+use core::ops::{Deref, DerefMut};
+use core::ptr;
+
+use clear::DeepClear;
+
+/// Wipes a value, and the heap memory it owns, when dropped.
+///
+/// Unlike `ClearOnDrop`, which clears the place a pointer refers to,
+/// this struct holds the value itself, and calls `DeepClear::deep_clear`
+/// on it before it is dropped. For a `Vec<T>` or `String`, this also
+/// wipes the spare capacity after the contents, before the buffer is
+/// returned to the allocator.
+///
+/// # Example
+///
+/// ```
+/// # use clear_on_drop::DeepClearOnDrop;
+/// # #[cfg(feature = "alloc")]
+/// # {
+/// let mut password = DeepClearOnDrop::new(String::with_capacity(64));
+/// password.push_str("hunter2");
+/// // ...
+/// let password = DeepClearOnDrop::into_value(password);
+/// assert_eq!(password, "\0\0\0\0\0\0\0");
+/// # }
+/// ```
+pub struct DeepClearOnDrop<T: DeepClear> {
+    _value: T,
+}
+
+impl<T: DeepClear> DeepClearOnDrop<T> {
+    /// Creates a new `DeepClearOnDrop` which wipes `value` on drop.
+    #[inline]
+    pub fn new(value: T) -> Self {
+        DeepClearOnDrop { _value: value }
+    }
+
+    /// Consumes the `DeepClearOnDrop`, returning the `value` after
+    /// wiping it.
+    ///
+    /// Note: this is an associated function, which means that you have
+    /// to call it as `DeepClearOnDrop::into_value(c)` instead of
+    /// `c.into_value()`. This is so that there is no conflict with a
+    /// method on the inner type.
+    #[inline]
+    pub fn into_value(mut c: Self) -> T {
+        c._value.deep_clear();
+        Self::into_uncleared_value(c)
+    }
+
+    /// Consumes the `DeepClearOnDrop`, returning the `value` without
+    /// wiping it.
+    ///
+    /// Note: this is an associated function, which means that you have
+    /// to call it as `DeepClearOnDrop::into_uncleared_value(c)` instead
+    /// of `c.into_uncleared_value()`. This is so that there is no
+    /// conflict with a method on the inner type.
+    #[inline]
+    pub fn into_uncleared_value(c: Self) -> T {
+        unsafe {
+            let value = ptr::read(&c._value);
+            mem::forget(c);
+            value
+        }
+    }
+}
+
+impl<T: DeepClear + fmt::Debug> fmt::Debug for DeepClearOnDrop<T> {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self._value, f)
+    }
+}
+
+impl<T: DeepClear> Deref for DeepClearOnDrop<T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        &self._value
+    }
+}
+
+impl<T: DeepClear> DerefMut for DeepClearOnDrop<T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self._value
+    }
+}
+
+impl<T: DeepClear> Drop for DeepClearOnDrop<T> {
+    #[inline]
+    fn drop(&mut self) {
+        self._value.deep_clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DeepClearOnDrop;
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn into_vec() {
+        let mut place = vec![0x41u8; 64];
+        place.truncate(16);
+        let ptr = place.as_ptr();
+
+        let clear = DeepClearOnDrop::new(place);
+        let place = DeepClearOnDrop::into_value(clear);
+        assert_eq!(place.as_ptr(), ptr);
+        let bytes = unsafe { ::core::slice::from_raw_parts(place.as_ptr(), place.capacity()) };
+        assert!(bytes.iter().all(|&b| b == 0));
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn into_uncleared_string() {
+        let mut clear = DeepClearOnDrop::new(String::new());
+        clear.push_str("test");
+
+        let place = DeepClearOnDrop::into_uncleared_value(clear);
+        assert_eq!(place, "test");
+    }
+
+    #[test]
+    fn on_array() {
+        let mut clear = DeepClearOnDrop::new([0u32; 4]);
+        clear[0] = 0x41414141;
+        assert_eq!(clear[0], 0x41414141);
+    }
+}
@@ -0,0 +1,347 @@
+use core::cell::Cell;
+use core::fmt;
+use core::mem;
+use core::ops::{Deref, DerefMut};
+use core::ptr;
+use core::slice;
+
+use libc;
+
+use clear::{Clear, InitializableFromZeroed};
+use locked::LockOptions;
+use sys::{page_size, round_to_pages, OsError, Region};
+
+/// A box which keeps its value between inaccessible guard pages, and
+/// makes the value itself inaccessible unless it is borrowed.
+///
+/// The value is placed at the end of its own locked pages, right before
+/// a guard page, with another guard page before them. These pages are
+/// protected with `PROT_NONE` except while a `GuardedRef` or
+/// `GuardedMut` returned by `borrow` or `borrow_mut` is alive, so stray
+/// reads of the value and buffer overruns into it crash the process
+/// with `SIGSEGV` instead of silently leaking or corrupting it. When
+/// dropped, the value is dropped, and the pages are cleared, unlocked
+/// and unmapped.
+///
+/// Each box takes at least three pages, and every borrow changes the
+/// page protection with a system call, so it is better suited to a few
+/// long-lived secrets than to many short-lived ones.
+///
+/// Since a `GuardedMut` dereferences to the value, it can be used as
+/// the place of a `ClearOnDrop`, to clear the value as soon as it is no
+/// longer needed.
+///
+/// # Example
+///
+/// ```
+/// # use clear_on_drop::{ClearOnDrop, GuardedBox};
+/// let mut key: GuardedBox<[u8; 32]> = GuardedBox::new_zeroed().unwrap();
+/// key.borrow_mut().copy_from_slice(&[0x41; 32]);
+/// assert_eq!(key.borrow()[0], 0x41);
+///
+/// let key = ClearOnDrop::new(key.borrow_mut());
+/// // ...
+/// drop(key);
+/// ```
+pub struct GuardedBox<T> {
+    region: Region,
+    ptr: *mut T,
+    readers: Cell<usize>,
+}
+
+impl<T> GuardedBox<T> {
+    /// Moves `value` into newly allocated guarded memory.
+    #[inline]
+    pub fn new(value: T) -> Result<Self, OsError> {
+        Self::new_with_options(value, LockOptions::new())
+    }
+
+    /// Moves `value` into newly allocated guarded memory, with the given
+    /// options.
+    pub fn new_with_options(value: T, options: LockOptions) -> Result<Self, OsError> {
+        let (region, ptr) = allocate::<T>(options)?;
+        unsafe {
+            ptr::write(ptr, value);
+        }
+        Ok(GuardedBox::from_raw(region, ptr))
+    }
+
+    /// Allocates guarded memory, and initializes it in place from
+    /// all-bits-zero.
+    #[inline]
+    pub fn new_zeroed() -> Result<Self, OsError>
+    where
+        T: InitializableFromZeroed,
+    {
+        Self::new_zeroed_with_options(LockOptions::new())
+    }
+
+    /// Allocates guarded memory with the given options, and initializes
+    /// it in place from all-bits-zero.
+    pub fn new_zeroed_with_options(options: LockOptions) -> Result<Self, OsError>
+    where
+        T: InitializableFromZeroed,
+    {
+        let (region, ptr) = allocate::<T>(options)?;
+        unsafe {
+            T::initialize(ptr);
+        }
+        Ok(GuardedBox::from_raw(region, ptr))
+    }
+
+    fn from_raw(region: Region, ptr: *mut T) -> Self {
+        let boxed = GuardedBox {
+            region,
+            ptr,
+            readers: Cell::new(0),
+        };
+        boxed.set_access(libc::PROT_NONE);
+        boxed
+    }
+
+    /// Makes the value readable until the returned guard is dropped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the page protection cannot be changed.
+    pub fn borrow(&self) -> GuardedRef<'_, T> {
+        let readers = self.readers.get();
+        if readers == 0 {
+            self.set_access(libc::PROT_READ);
+        }
+        self.readers.set(readers + 1);
+        GuardedRef { boxed: self }
+    }
+
+    /// Makes the value readable and writable until the returned guard
+    /// is dropped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the page protection cannot be changed.
+    pub fn borrow_mut(&mut self) -> GuardedMut<'_, T> {
+        self.set_access(libc::PROT_READ | libc::PROT_WRITE);
+        GuardedMut { boxed: self }
+    }
+
+    /// Changes the protection of the pages between the guard pages.
+    fn set_access(&self, prot: libc::c_int) {
+        let page_size = page_size();
+        self.region
+            .protect(page_size, self.region.len() - 2 * page_size, prot)
+            .expect("cannot change the protection of a GuardedBox");
+    }
+}
+
+impl<T> Drop for GuardedBox<T> {
+    fn drop(&mut self) {
+        self.set_access(libc::PROT_READ | libc::PROT_WRITE);
+        unsafe {
+            ptr::drop_in_place(self.ptr);
+        }
+        let page_size = page_size();
+        let len = self.region.len() - 2 * page_size;
+        unsafe { slice::from_raw_parts_mut(self.region.as_ptr().add(page_size), len) }.clear();
+    }
+}
+
+unsafe impl<T: Send> Send for GuardedBox<T> {}
+
+/// Shared access to the value of a `GuardedBox`.
+///
+/// The value stays readable while any of these is alive.
+pub struct GuardedRef<'a, T: 'a> {
+    boxed: &'a GuardedBox<T>,
+}
+
+impl<'a, T> Drop for GuardedRef<'a, T> {
+    fn drop(&mut self) {
+        let readers = self.boxed.readers.get() - 1;
+        self.boxed.readers.set(readers);
+        if readers == 0 {
+            self.boxed.set_access(libc::PROT_NONE);
+        }
+    }
+}
+
+impl<'a, T> Deref for GuardedRef<'a, T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        unsafe { &*self.boxed.ptr }
+    }
+}
+
+impl<'a, T: fmt::Debug> fmt::Debug for GuardedRef<'a, T> {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+/// Exclusive access to the value of a `GuardedBox`.
+///
+/// The value stays readable and writable while this is alive.
+pub struct GuardedMut<'a, T: 'a> {
+    boxed: &'a mut GuardedBox<T>,
+}
+
+impl<'a, T> Drop for GuardedMut<'a, T> {
+    #[inline]
+    fn drop(&mut self) {
+        self.boxed.set_access(libc::PROT_NONE);
+    }
+}
+
+impl<'a, T> Deref for GuardedMut<'a, T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        unsafe { &*self.boxed.ptr }
+    }
+}
+
+impl<'a, T> DerefMut for GuardedMut<'a, T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.boxed.ptr }
+    }
+}
+
+impl<'a, T: fmt::Debug> fmt::Debug for GuardedMut<'a, T> {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+/// Maps and locks zeroed memory for a `T` between two guard pages,
+/// placing the `T` as close to the second guard page as its alignment
+/// allows.
+fn allocate<T>(options: LockOptions) -> Result<(Region, *mut T), OsError> {
+    let page_size = page_size();
+    assert!(mem::align_of::<T>() <= page_size);
+    let len = round_to_pages(mem::size_of::<T>());
+    let region = options.map_with_guards(len, page_size)?;
+    let end = region.as_ptr() as usize + page_size + len;
+    let ptr = (end - mem::size_of::<T>()) & !(mem::align_of::<T>() - 1);
+    Ok((region, ptr as *mut T))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GuardedBox;
+    use budget::lock_report;
+    use sys::page_size;
+    use sys::tests::in_child;
+    use ClearOnDrop;
+
+    use core::ptr;
+
+    use libc;
+
+    #[derive(Default)]
+    struct Place {
+        data: [u32; 4],
+    }
+
+    const DATA: [u32; 4] = [0x01234567, 0x89abcdef, 0xfedcba98, 0x76543210];
+
+    // Runs `f` in a forked child, and returns whether it was killed by
+    // a SIGSEGV.
+    fn segfaults<F: FnOnce()>(f: F) -> bool {
+        let pid = unsafe { libc::fork() };
+        assert!(pid >= 0);
+        if pid == 0 {
+            f();
+            unsafe { libc::_exit(0) };
+        }
+        let mut status = 0;
+        assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+        libc::WIFSIGNALED(status) && libc::WTERMSIG(status) == libc::SIGSEGV
+    }
+
+    #[test]
+    fn guarded_box() {
+        let mut place = GuardedBox::new(Place { data: DATA }).unwrap();
+        assert_eq!(place.borrow().data, DATA);
+        place.borrow_mut().data[0] = 0;
+        assert_eq!(place.borrow().data[0], 0);
+    }
+
+    #[test]
+    fn guarded_box_zeroed() {
+        let place: GuardedBox<Place> = GuardedBox::new_zeroed().unwrap();
+        assert_eq!(place.borrow().data, [0; 4]);
+    }
+
+    #[test]
+    fn guarded_box_drops_value() {
+        let value = ::std::rc::Rc::new(());
+        let place = GuardedBox::new(value.clone()).unwrap();
+        assert_eq!(::std::rc::Rc::strong_count(&value), 2);
+        drop(place);
+        assert_eq!(::std::rc::Rc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn locks_only_the_value() {
+        // In a child, so no other test changes the report meanwhile.
+        assert!(in_child(|| {
+            let before = lock_report();
+            let place = GuardedBox::new(Place { data: DATA }).unwrap();
+            let after = lock_report();
+            drop(place);
+            after.locked_bytes + after.unlocked_bytes
+                == before.locked_bytes + before.unlocked_bytes + page_size()
+                && lock_report() == before
+        }));
+    }
+
+    #[test]
+    fn into_clear_on_drop() {
+        let mut place = GuardedBox::new(Place { data: DATA }).unwrap();
+        drop(ClearOnDrop::new(place.borrow_mut()));
+        assert_eq!(place.borrow().data, [0; 4]);
+    }
+
+    #[test]
+    fn inaccessible_unless_borrowed() {
+        let place = GuardedBox::new(Place { data: DATA }).unwrap();
+        let ptr = place.ptr;
+        assert!(segfaults(|| unsafe {
+            ptr::read_volatile(ptr);
+        }));
+
+        let first = place.borrow();
+        {
+            let second = place.borrow();
+            assert_eq!(second.data, DATA);
+        }
+        assert!(!segfaults(|| unsafe {
+            ptr::read_volatile(ptr);
+        }));
+        drop(first);
+        assert!(segfaults(|| unsafe {
+            ptr::read_volatile(ptr);
+        }));
+    }
+
+    #[test]
+    fn overrun_segfaults() {
+        let mut place: GuardedBox<[u8; 13]> = GuardedBox::new_zeroed().unwrap();
+        let mut guard = place.borrow_mut();
+        let ptr = guard.as_mut_ptr();
+        assert!(!segfaults(|| unsafe {
+            ptr::write_volatile(ptr.add(12), 0x41)
+        }));
+        assert!(segfaults(|| unsafe {
+            ptr::write_volatile(ptr.add(13), 0x41)
+        }));
+        assert!(segfaults(|| unsafe {
+            ptr::write_volatile(ptr.sub(::sys::page_size()), 0x41)
+        }));
+    }
+}
@@ -0,0 +1,242 @@
+use libc;
+
+use sys::{parse_decimal, read_file, OsError};
+
+/// Options for `harden_process_with_options`.
+///
+/// # Example
+///
+/// ```
+/// # use clear_on_drop::{harden_process_with_options, HardenOptions};
+/// let options = HardenOptions::new().disable_core_dumps(false);
+/// let report = harden_process_with_options(options);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HardenOptions {
+    non_dumpable: bool,
+    disable_core_dumps: bool,
+}
+
+impl HardenOptions {
+    /// Creates the default options, which are used by `harden_process`.
+    #[inline]
+    pub fn new() -> Self {
+        HardenOptions {
+            non_dumpable: true,
+            disable_core_dumps: true,
+        }
+    }
+
+    /// Sets whether the process is made non-dumpable, with
+    /// `PR_SET_DUMPABLE`. Enabled by default.
+    ///
+    /// A non-dumpable process does not write core dumps, and only
+    /// processes with the `CAP_SYS_PTRACE` capability can attach to it
+    /// with a debugger or read its memory through `/proc`. This is the
+    /// protection against `ptrace`: it applies whether or not the Yama
+    /// security module is enabled, and also overrides any exception the
+    /// process made to the Yama restrictions with `PR_SET_PTRACER`.
+    #[inline]
+    pub fn non_dumpable(mut self, enable: bool) -> Self {
+        self.non_dumpable = enable;
+        self
+    }
+
+    /// Sets whether core dumps are disabled, by setting both the soft
+    /// and the hard `RLIMIT_CORE` to zero, so they cannot be raised
+    /// again without privileges. Enabled by default.
+    #[inline]
+    pub fn disable_core_dumps(mut self, enable: bool) -> Self {
+        self.disable_core_dumps = enable;
+        self
+    }
+}
+
+impl Default for HardenOptions {
+    #[inline]
+    fn default() -> Self {
+        HardenOptions::new()
+    }
+}
+
+/// The outcome of one of the protections of `harden_process`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Protection {
+    /// The protection was applied.
+    Applied,
+    /// The protection was not requested.
+    Skipped,
+    /// The protection could not be applied.
+    Failed(OsError),
+}
+
+impl Protection {
+    /// Returns whether the protection was applied.
+    #[inline]
+    pub fn is_applied(&self) -> bool {
+        *self == Protection::Applied
+    }
+
+    #[inline]
+    fn from_result(result: Result<(), OsError>) -> Self {
+        match result {
+            Ok(()) => Protection::Applied,
+            Err(err) => Protection::Failed(err),
+        }
+    }
+}
+
+/// Which protections `harden_process` applied.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HardenReport {
+    /// Whether the process was made non-dumpable.
+    pub non_dumpable: Protection,
+    /// Whether core dumps were disabled.
+    pub core_dumps_disabled: Protection,
+    /// The Yama `ptrace_scope` of the system, or `None` if Yama is not
+    /// enabled. From 1 up, it restricts which processes can attach to
+    /// any process; see the kernel documentation for the details.
+    pub ptrace_scope: Option<u32>,
+}
+
+impl HardenReport {
+    /// Returns whether none of the requested protections failed.
+    pub fn is_complete(&self) -> bool {
+        [self.non_dumpable, self.core_dumps_disabled]
+            .iter()
+            .all(|protection| !matches!(*protection, Protection::Failed(_)))
+    }
+}
+
+/// Protects the process against debuggers and core dumps, which could
+/// read secrets from its memory.
+///
+/// This makes the process non-dumpable, which keeps debuggers from
+/// attaching to it, and disables core dumps, with the default
+/// `HardenOptions`. It should be called early at startup,
+/// before any secret is loaded; the protections apply to the whole
+/// process, and last until it calls `execve`.
+///
+/// Failures do not stop the other protections from being applied, and
+/// are returned in the report, so the caller can decide whether to
+/// continue without them.
+///
+/// # Example
+///
+/// ```
+/// # use clear_on_drop::harden_process;
+/// let report = harden_process();
+/// if !report.is_complete() {
+///     eprintln!("running without full protection: {:?}", report);
+/// }
+/// ```
+pub fn harden_process() -> HardenReport {
+    harden_process_with_options(HardenOptions::new())
+}
+
+/// Protects the process against debuggers and core dumps, with the
+/// given options.
+///
+/// See `harden_process` for the details.
+pub fn harden_process_with_options(options: HardenOptions) -> HardenReport {
+    HardenReport {
+        non_dumpable: apply(options.non_dumpable, set_non_dumpable),
+        core_dumps_disabled: apply(options.disable_core_dumps, disable_core_dumps),
+        ptrace_scope: ptrace_scope(),
+    }
+}
+
+#[inline]
+fn apply(enable: bool, f: fn() -> Result<(), OsError>) -> Protection {
+    if enable {
+        Protection::from_result(f())
+    } else {
+        Protection::Skipped
+    }
+}
+
+fn set_non_dumpable() -> Result<(), OsError> {
+    prctl(PR_SET_DUMPABLE, 0)
+}
+
+fn disable_core_dumps() -> Result<(), OsError> {
+    let limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    if unsafe { libc::setrlimit(libc::RLIMIT_CORE, &limit) } != 0 {
+        return Err(OsError::last("setrlimit"));
+    }
+    Ok(())
+}
+
+// The `libc` crate only defines these for Android since 0.2.175, which
+// needs a newer compiler than this crate; they are the same everywhere.
+#[cfg(test)]
+const PR_GET_DUMPABLE: libc::c_int = 3;
+const PR_SET_DUMPABLE: libc::c_int = 4;
+
+fn prctl(option: libc::c_int, arg: libc::c_ulong) -> Result<(), OsError> {
+    if unsafe {
+        libc::prctl(
+            option,
+            arg,
+            0 as libc::c_ulong,
+            0 as libc::c_ulong,
+            0 as libc::c_ulong,
+        )
+    } != 0
+    {
+        return Err(OsError::last("prctl"));
+    }
+    Ok(())
+}
+
+/// Reads `/proc/sys/kernel/yama/ptrace_scope`.
+fn ptrace_scope() -> Option<u32> {
+    let mut buf = [0u8; 16];
+    let scope = read_file(b"/proc/sys/kernel/yama/ptrace_scope\0", &mut buf)?;
+    parse_decimal(scope).map(|scope| scope as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        harden_process, harden_process_with_options, HardenOptions, Protection, PR_GET_DUMPABLE,
+    };
+    use libc;
+    use sys::tests::in_child;
+
+    // The protections cannot be undone, so they are applied in children.
+
+    #[test]
+    fn harden() {
+        assert!(in_child(|| {
+            let report = harden_process();
+            let mut limit = libc::rlimit {
+                rlim_cur: 1,
+                rlim_max: 1,
+            };
+            unsafe { libc::getrlimit(libc::RLIMIT_CORE, &mut limit) };
+            report.is_complete()
+                && report.non_dumpable.is_applied()
+                && report.core_dumps_disabled.is_applied()
+                && unsafe { libc::prctl(PR_GET_DUMPABLE) } == 0
+                && limit.rlim_cur == 0
+                && limit.rlim_max == 0
+        }));
+    }
+
+    #[test]
+    fn skipped() {
+        assert!(in_child(|| {
+            let options = HardenOptions::new()
+                .non_dumpable(false)
+                .disable_core_dumps(false);
+            let report = harden_process_with_options(options);
+            report.non_dumpable == Protection::Skipped
+                && report.core_dumps_disabled == Protection::Skipped
+                && unsafe { libc::prctl(PR_GET_DUMPABLE) } == 1
+        }));
+    }
+}
@@ -7,6 +7,9 @@
 //! Inspired by/based on Linux kernel's `OPTIMIZER_HIDE_VAR`, which in
 //! turn was based on the earlier `RELOC_HIDE` macro.
 
+#[cfg(not(clear_on_drop_hide = "volatile"))]
+use core::ptr;
+
 /// Make the optimizer believe the memory pointed to by `ptr` is read
 /// and modified arbitrarily.
 #[inline]
@@ -22,44 +25,88 @@ pub fn hide_ptr<P>(mut ptr: P) -> P {
     ptr
 }
 
-pub use self::impls::hide_mem_impl;
+pub(crate) use self::impls::hide_mem_impl;
 
-// On nightly, inline assembly can be used.
-#[cfg(feature = "nightly")]
-mod impls {
-    trait HideMemImpl {
-        fn hide_mem_impl(ptr: *mut Self);
-    }
+/// Overwrites `len` bytes at `ptr` with zeros, in a way the optimizer
+/// cannot remove.
+#[cfg(not(clear_on_drop_hide = "volatile"))]
+#[inline]
+pub(crate) unsafe fn clear_mem(ptr: *mut u8, len: usize) {
+    ptr::write_bytes(ptr, 0, len);
+    hide_mem_impl::<[u8]>(ptr::slice_from_raw_parts_mut(ptr, len));
+}
 
-    impl<T: ?Sized> HideMemImpl for T {
-        #[inline]
-        default fn hide_mem_impl(ptr: *mut Self) {
-            unsafe {
-                llvm_asm!("" : : "r" (ptr as *mut u8) : "memory");
-                // asm!("", in(reg) (ptr as *mut u8), options(nostack));
-            }
-        }
-    }
+#[cfg(clear_on_drop_hide = "volatile")]
+pub(crate) use self::impls::clear_mem;
 
-    impl<T: Sized> HideMemImpl for T {
-        #[inline]
-        fn hide_mem_impl(ptr: *mut Self) {
-            unsafe {
-                llvm_asm!("" : "=*m" (ptr) : "*0" (ptr));
-                // asm!("", in(reg) ptr, options(nostack));
-            }
-        }
-    }
+/// A mechanism used to hide values from the optimizer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Backend {
+    /// An empty inline assembly statement, which receives the pointer.
+    Asm,
+    /// A call to a dummy C function, which the optimizer cannot see into.
+    CFunction,
+    /// A store of the pointer to an atomic variable. This is a fallback,
+    /// which is less reliable than the others.
+    Atomic,
+    /// Volatile writes of zeros, followed by a compiler fence, to clear
+    /// memory; other uses of `hide_mem` and `hide_ptr` work like
+    /// `Atomic`.
+    Volatile,
+}
+
+/// The mechanism used by `hide_mem` and `hide_ptr`, which was selected
+/// when building this crate.
+pub const ACTIVE_BACKEND: Backend = self::impls::BACKEND;
+
+/// Returns the mechanism used by `hide_mem` and `hide_ptr`, which was
+/// selected when building this crate.
+///
+/// # Example
+///
+/// ```
+/// use clear_on_drop::hide::{self, Backend};
+///
+/// if hide::active_backend() == Backend::Atomic {
+///     println!("warning: clearing might be optimized away");
+/// }
+/// ```
+#[inline]
+pub fn active_backend() -> Backend {
+    ACTIVE_BACKEND
+}
+
+// The build script sets `clear_on_drop_hide` to the strongest of the
+// mechanisms below which works for the target.
+
+// When inline assembly is available (Rust 1.59 or newer, on a supported
+// architecture), an empty asm statement receiving the pointer can be
+// used. Since it is not marked as `nomem` or `readonly`, the compiler
+// has to assume it reads and writes the memory behind the pointer. The
+// pointer is cast to a thin pointer, so unsized types work too.
+#[cfg(clear_on_drop_hide = "asm")]
+mod impls {
+    use super::Backend;
+    use core::arch::asm;
+
+    pub const BACKEND: Backend = Backend::Asm;
 
     #[inline]
     pub fn hide_mem_impl<T: ?Sized>(ptr: *mut T) {
-        HideMemImpl::hide_mem_impl(ptr)
+        unsafe {
+            asm!("/* {0} */", in(reg) ptr as *mut u8, options(nostack, preserves_flags));
+        }
     }
 }
 
 // When a C compiler is available, a dummy C function can be used.
-#[cfg(not(feature = "no_cc"))]
+#[cfg(clear_on_drop_hide = "cc")]
 mod impls {
+    use super::Backend;
+
+    pub const BACKEND: Backend = Backend::CFunction;
+
     extern "C" {
         fn clear_on_drop_hide(ptr: *mut u8) -> *mut u8;
     }
@@ -74,19 +121,69 @@ mod impls {
 
 // When neither is available, pretend the pointer is sent to a thread,
 // and hope this is enough to confuse the optimizer.
-#[cfg(all(feature = "no_cc", not(feature = "nightly")))]
+#[cfg(clear_on_drop_hide = "atomic")]
+mod impls {
+    use super::Backend;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    pub const BACKEND: Backend = Backend::Atomic;
+
+    #[inline(never)]
+    pub fn hide_mem_impl<T: ?Sized>(ptr: *mut T) {
+        // Otherwise the optimizer removes a static which is only ever
+        // stored to, and this function becomes empty.
+        #[used]
+        static DUMMY: AtomicUsize = AtomicUsize::new(0);
+        DUMMY.store(ptr as *mut u8 as usize, Ordering::Release);
+    }
+}
+
+// Alternatively, memory can be cleared with volatile writes, which the
+// optimizer must not remove, followed by a compiler fence to keep them
+// from being reordered after the memory is freed or reused.
+#[cfg(clear_on_drop_hide = "volatile")]
 mod impls {
-    use core::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+    use super::Backend;
+    use core::mem;
+    use core::ptr;
+    use core::sync::atomic::{compiler_fence, AtomicUsize, Ordering};
+
+    pub const BACKEND: Backend = Backend::Volatile;
 
     #[inline(never)]
     pub fn hide_mem_impl<T: ?Sized>(ptr: *mut T) {
-        static DUMMY: AtomicUsize = ATOMIC_USIZE_INIT;
+        static DUMMY: AtomicUsize = AtomicUsize::new(0);
         DUMMY.store(ptr as *mut u8 as usize, Ordering::Release);
+        compiler_fence(Ordering::SeqCst);
+    }
+
+    #[inline]
+    pub unsafe fn clear_mem(ptr: *mut u8, len: usize) {
+        const WORD: usize = mem::size_of::<usize>();
+
+        let end = ptr as usize + len;
+        let mut pos = ptr as usize;
+        // Unaligned head and tail bytes are written one at a time.
+        while pos < end && pos & (WORD - 1) != 0 {
+            ptr::write_volatile(pos as *mut u8, 0);
+            pos += 1;
+        }
+        while end - pos >= WORD {
+            ptr::write_volatile(pos as *mut usize, 0);
+            pos += WORD;
+        }
+        while pos < end {
+            ptr::write_volatile(pos as *mut u8, 0);
+            pos += 1;
+        }
+        compiler_fence(Ordering::SeqCst);
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::{active_backend, clear_mem, Backend, ACTIVE_BACKEND};
+
     struct Place {
         data: [u32; 4],
     }
@@ -108,4 +205,33 @@ mod tests {
         assert_eq!(before, after as *mut _);
         assert_eq!(after.data, DATA);
     }
+
+    #[test]
+    fn backend() {
+        assert_eq!(active_backend(), ACTIVE_BACKEND);
+        if cfg!(feature = "volatile") {
+            assert_eq!(active_backend(), Backend::Volatile);
+        } else if cfg!(feature = "no_asm") {
+            assert_ne!(active_backend(), Backend::Asm);
+        } else if cfg!(target_arch = "x86_64") {
+            assert_eq!(active_backend(), Backend::Asm);
+        }
+        if cfg!(feature = "no_cc") {
+            assert_ne!(active_backend(), Backend::CFunction);
+        }
+    }
+
+    #[test]
+    fn clear_mem_unaligned() {
+        let mut buf = [0x41u8; 64];
+        for start in 0..8 {
+            for len in 0..40 {
+                unsafe { clear_mem(buf.as_mut_ptr().add(start), len) };
+                assert!(buf[..start].iter().all(|&b| b == 0x41));
+                assert!(buf[start..start + len].iter().all(|&b| b == 0));
+                assert!(buf[start + len..].iter().all(|&b| b == 0x41));
+                buf = [0x41; 64];
+            }
+        }
+    }
 }
@@ -1,5 +1,4 @@
 #![cfg_attr(not(test), no_std)]
-#![cfg_attr(feature = "nightly", feature(llvm_asm, i128_type, specialization))]
 #![deny(missing_docs)]
 
 //! Helpers for clearing sensitive data on the stack and heap.
@@ -22,15 +21,60 @@
 //! dropped. While the mutable reference is held, the data cannot be
 //! moved, so there won't be leftovers due to moves; the wrapper itself
 //! can be freely moved. Alternatively, it can hold data on the heap
-//! (using a `Box<T>`, or possibly a similar which allocates from a
-//! `mlock`ed heap).
+//! (using a `Box<T>`, or on Linux a `LockedBox<T>`, which allocates
+//! from `mlock`ed memory which is never written to swap). For the most
+//! sensitive values, a `GuardedBox<T>` also surrounds the value with
+//! guard pages, and keeps it inaccessible except while it is borrowed,
+//! and a `SecretMemBox<T>` allocates from `memfd_secret`, which hides the
+//! value even from the kernel, when it is available.
+//! A `DeepClearOnDrop` instead holds the value itself, and also wipes
+//! the heap memory it owns, such as the spare capacity of a `Vec<T>` or
+//! `String`, before it is freed.
+//! Many small secrets can share the locked memory of a `SecretArena`.
+//! When locking fails, for instance because of `RLIMIT_MEMLOCK`, a
+//! `LockPolicy` chooses whether to fail, warn, or silently fall back to
+//! unlocked memory; `memlock_budget` and `lock_report` tell how much
+//! memory can still be locked, and how much secret memory is unlocked.
+//!
+//! To keep debuggers and core dumps away from the secrets in the first
+//! place, `harden_process` can be called at startup; on Linux, it makes
+//! the process non-dumpable, which keeps debuggers from attaching to it,
+//! and disables core dumps, reporting which of these protections could
+//! be applied.
+//!
+//! The `SecretVec` and `SecretString` types (with the `alloc` feature)
+//! are growable buffers which, unlike a `Vec<T>` or `String` held by a
+//! `ClearOnDrop`, also clear the old allocations they leave behind when
+//! they grow or shrink.
 //!
 //! The `clear_stack_on_return` function calls a closure, and after it
 //! returns, overwrites several kilobytes of the stack. This can help
 //! overwrite temporary variables used by cryptographic algorithms, and
 //! is especially relevant when running on a short-lived thread, since
 //! the memory used for the thread stack cannot be easily overwritten
-//! after the thread terminates.
+//! after the thread terminates. The amount is given in pages of
+//! `page_size()` bytes, or in bytes with `clear_stack_on_return_bytes`;
+//! `clear_stack_on_return_measured` measures how much stack a closure
+//! actually uses, and `clear_stack_on_return_auto` uses that measurement
+//! to clear just enough. On Linux and Android, no more is cleared than
+//! fits in the thread stack, so a large amount cannot overflow it, and
+//! `try_clear_stack_on_return` returns an error, without calling the
+//! closure, when the requested amount does not fit.
+//!
+//! Secrets can also be left in CPU registers, especially in the vector
+//! registers used by accelerated cryptographic algorithms. On x86_64 and
+//! aarch64, `clear_registers` overwrites the caller-saved registers,
+//! which is also done after the closure returns when using
+//! `clear_stack_on_return_with_options` with the `clear_registers`
+//! option.
+//!
+//! Temporary buffers allocated and freed deep inside other crates can be
+//! cleared by using a `ClearingAllocator` as the global allocator; it
+//! wraps another allocator, and clears every block of memory it frees.
+//! With the `std` feature, `clear_heap_and_stack_on_return` is a more
+//! targeted alternative: with a `ScopedClearingAllocator` as the global
+//! allocator, it clears both the stack used by a closure, and every
+//! block of memory the closure allocated, whenever it is freed.
 //!
 //! # Preventing compiler optimizations
 //!
@@ -38,24 +82,46 @@
 //! it could elide the clearing code. Aditionally, the compiler could
 //! inline a called function and the stack clearing code, using separate
 //! areas of the stack for each. This crate has three mechanisms which
-//! prevent these unwanted optimizations, selected at compile time via
-//! cargo features.
+//! prevent these unwanted optimizations; the build script selects the
+//! strongest one which works at compile time.
 //!
-//! The fastest mechanism uses inline assembly, which is only available
-//! on nightly Rust. It is enabled through the `nightly` feature, and
-//! does not need a working C compiler.
+//! The fastest mechanism uses inline assembly, which is available on
+//! Rust 1.59 or newer for the x86, x86_64, aarch64, arm and riscv
+//! architectures, and does not need a working C compiler. On x86_64 and
+//! aarch64, inline assembly is also used to overwrite the stack in a
+//! single frame, instead of recursing once for every 4 KiB. Both can be
+//! skipped with the `no_asm` feature.
 //!
-//! The second mechanism, which is the default, uses a call to a dummy
-//! C function. It works on stable Rust, but needs a working C compiler.
+//! Otherwise, the second mechanism uses a call to a dummy C function,
+//! when a working C compiler is found. It can be skipped with the
+//! `no_cc` feature (also enabled by the `nightly` feature, which is kept
+//! for compatibility).
 //!
 //! The third mechanism is a fallback, which attempts to confuse the
-//! optimizer through the use of atomic instructions. It should not be
-//! used unless necessary, since it's less reliable. It is enabled by
-//! the `no_cc` feature, works on stable Rust, and does not need a C
-//! compiler.
+//! optimizer through the use of atomic instructions. It is used only
+//! when neither of the others is available, since it's less reliable.
+//!
+//! Alternatively, the `volatile` feature clears memory with volatile
+//! writes, followed by a compiler fence, which needs neither inline
+//! assembly nor a C compiler. It is slower, especially for
+//! `clear_stack_on_return`, since every word is written separately.
+//!
+//! The `hide::active_backend` function tells which mechanism was selected.
+//!
+//! # Deriving the `clear` traits
+//!
+//! With the `derive` feature, the `clear` module also exports derive
+//! macros for `ZeroSafe`, `InitializableFromZeroed` and `DeepClear`, so
+//! types which do not implement `Default` can be cleared without writing
+//! any unsafe code. See the `clear_on_drop_derive` crate for the details. The macros are
+//! built with syn 2, so this feature needs Rust 1.56 or newer, while the
+//! rest of the crate builds with Rust 1.51.
 
 #![cfg_attr(all(feature = "mesalock_sgx", not(target_env = "sgx")), no_std)]
-#![cfg_attr(all(target_env = "sgx", target_vendor = "mesalock"), feature(rustc_private))]
+#![cfg_attr(
+    all(target_env = "sgx", target_vendor = "mesalock"),
+    feature(rustc_private)
+)]
 
 #[cfg(all(feature = "mesalock_sgx", not(target_env = "sgx")))]
 #[macro_use]
@@ -64,11 +130,79 @@ extern crate sgx_tstd as std;
 #[cfg(test)]
 extern crate core;
 
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+#[cfg(all(feature = "std", not(test), not(feature = "mesalock_sgx")))]
+extern crate std;
+
+#[cfg(feature = "derive")]
+extern crate clear_on_drop_derive;
+
+#[cfg(unix)]
+extern crate libc;
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod arena;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod budget;
 pub mod clear;
+#[cfg(feature = "std")]
+mod clear_heap_on_return;
 mod clear_on_drop;
+mod clear_registers;
 mod clear_stack_on_return;
+mod clearing_allocator;
+mod deep_clear_on_drop;
 mod fnoption;
-mod hide;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod guarded;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod harden;
+pub mod hide;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod locked;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod secret_mem;
+#[cfg(feature = "alloc")]
+mod secret_string;
+#[cfg(feature = "alloc")]
+mod secret_vec;
+mod stack_depth;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod sys;
 
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub use arena::*;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub use budget::*;
+#[cfg(feature = "std")]
+pub use clear_heap_on_return::*;
 pub use clear_on_drop::*;
+pub use clear_registers::*;
 pub use clear_stack_on_return::*;
+pub use clearing_allocator::*;
+pub use deep_clear_on_drop::*;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub use guarded::*;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub use harden::*;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub use locked::*;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub use secret_mem::*;
+#[cfg(feature = "alloc")]
+pub use secret_string::*;
+#[cfg(feature = "alloc")]
+pub use secret_vec::*;
+pub use stack_depth::*;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub use sys::{exclude_from_core_dump, OsError};
+
+// Used by the code generated by the derive macros.
+#[cfg(feature = "derive")]
+#[doc(hidden)]
+pub mod __private {
+    pub use core::default::Default;
+    pub use core::ptr::{addr_of_mut, write};
+}
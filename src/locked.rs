@@ -0,0 +1,531 @@
+use core::fmt;
+use core::mem;
+use core::ops::{Deref, DerefMut};
+use core::ptr;
+use core::slice;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use libc;
+
+use clear::{InitializableFromZeroed, ZeroSafe};
+use sys::{page_size, round_to_pages, OsError, Region};
+
+/// What to do when memory cannot be locked into RAM.
+///
+/// Locking fails when the process would exceed its `RLIMIT_MEMLOCK`
+/// (see `memlock_budget`). Memory which could not be locked is still
+/// excluded from core dumps and wiped in forked children as requested,
+/// and counted in the `lock_report`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum LockPolicy {
+    /// Fail the allocation with the error from `mlock`. This is the
+    /// default.
+    Fail,
+    /// Use the memory without locking it, and print a warning to the
+    /// standard error the first time this happens in the process.
+    Warn,
+    /// Silently use the memory without locking it.
+    Fallback,
+}
+
+impl Default for LockPolicy {
+    #[inline]
+    fn default() -> Self {
+        LockPolicy::Fail
+    }
+}
+
+/// Options for allocating locked memory.
+///
+/// # Example
+///
+/// ```
+/// # use clear_on_drop::{LockedBox, LockOptions};
+/// let options = LockOptions::new().exclude_from_core_dump(false);
+/// let place = LockedBox::new_with_options([0u8; 32], options).unwrap();
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LockOptions {
+    policy: LockPolicy,
+    exclude_from_core_dump: bool,
+    wipe_on_fork: bool,
+}
+
+impl LockOptions {
+    /// Creates the default options.
+    #[inline]
+    pub fn new() -> Self {
+        LockOptions {
+            policy: LockPolicy::Fail,
+            exclude_from_core_dump: true,
+            wipe_on_fork: true,
+        }
+    }
+
+    /// Sets what to do when the memory cannot be locked.
+    #[inline]
+    pub fn lock_policy(mut self, policy: LockPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Sets whether the memory is excluded from core dumps, using
+    /// `MADV_DONTDUMP`. Enabled by default.
+    #[inline]
+    pub fn exclude_from_core_dump(mut self, exclude: bool) -> Self {
+        self.exclude_from_core_dump = exclude;
+        self
+    }
+
+    /// Sets whether the memory is replaced by zeros in children created
+    /// by `fork`, using `MADV_WIPEONFORK`, or on older kernels a
+    /// `pthread_atfork` handler. Enabled by default.
+    ///
+    /// In the child, the value reads as all-bits-zero, so unless that
+    /// is a valid value for its type, the child must not use or drop it;
+    /// usually the child calls `exec` or `_exit` instead.
+    #[inline]
+    pub fn wipe_on_fork(mut self, wipe: bool) -> Self {
+        self.wipe_on_fork = wipe;
+        self
+    }
+
+    /// Maps and locks `len` bytes of zeroed memory, rounded up to whole
+    /// pages, and applies the options to them.
+    #[inline]
+    pub(crate) fn map(&self, len: usize) -> Result<Region, OsError> {
+        self.map_with_guards(len, 0)
+    }
+
+    /// Like `map`, but surrounds the memory with `guard` bytes on each
+    /// side, a multiple of the page size, which are inaccessible and
+    /// not locked.
+    pub(crate) fn map_with_guards(&self, len: usize, guard: usize) -> Result<Region, OsError> {
+        let len = round_to_pages(len);
+        let size = guard
+            .checked_mul(2)
+            .and_then(|guards| guards.checked_add(len))
+            .expect("capacity overflow");
+        let mut region = Region::map(size)?;
+        if guard != 0 {
+            region.protect(0, guard, libc::PROT_NONE)?;
+            region.protect(guard + len, guard, libc::PROT_NONE)?;
+            region.set_lock_range(guard, len);
+        }
+        if let Err(err) = region.lock() {
+            match self.policy {
+                LockPolicy::Fail => return Err(err),
+                LockPolicy::Warn => warn_unlocked(),
+                LockPolicy::Fallback => {}
+            }
+            region.keep_unlocked();
+        }
+        if self.exclude_from_core_dump {
+            region.dont_dump()?;
+        }
+        if self.wipe_on_fork {
+            region.wipe_on_fork()?;
+        }
+        Ok(region)
+    }
+}
+
+/// Prints the warning for `LockPolicy::Warn`, unless it was already
+/// printed; `lock_report` counts every failure.
+fn warn_unlocked() {
+    static WARNED: AtomicBool = AtomicBool::new(false);
+    const WARNING: &[u8] =
+        b"clear_on_drop: cannot lock secret memory into RAM, it may be written to swap\n";
+    if WARNED.swap(true, Ordering::Relaxed) {
+        return;
+    }
+    unsafe {
+        libc::write(
+            libc::STDERR_FILENO,
+            WARNING.as_ptr() as *const _,
+            WARNING.len(),
+        );
+    }
+}
+
+impl Default for LockOptions {
+    #[inline]
+    fn default() -> Self {
+        LockOptions::new()
+    }
+}
+
+/// A box which allocates from memory locked into RAM.
+///
+/// The value is placed in its own pages, which are locked with `mlock`
+/// so they are never written to swap, and by default excluded from core
+/// dumps and wiped in forked children. When dropped, the value is
+/// dropped, and the pages are cleared, unlocked and unmapped.
+///
+/// Since it dereferences to the value, it can be used as the place of
+/// a `ClearOnDrop`, which clears the value earlier, when the
+/// `ClearOnDrop` is dropped.
+///
+/// Moving a value into the box with `new` leaves its old copy behind;
+/// use `new_zeroed` and fill the value in place to avoid that.
+///
+/// # Example
+///
+/// ```
+/// # use clear_on_drop::{ClearOnDrop, LockedBox};
+/// let place: LockedBox<[u8; 32]> = LockedBox::new_zeroed().unwrap();
+/// let mut key = ClearOnDrop::new(place);
+/// key.copy_from_slice(&[0x41; 32]);
+/// ```
+pub struct LockedBox<T> {
+    region: Region,
+    ptr: *mut T,
+}
+
+impl<T> LockedBox<T> {
+    /// Moves `value` into newly allocated locked memory.
+    #[inline]
+    pub fn new(value: T) -> Result<Self, OsError> {
+        Self::new_with_options(value, LockOptions::new())
+    }
+
+    /// Moves `value` into newly allocated locked memory, with the given
+    /// options.
+    pub fn new_with_options(value: T, options: LockOptions) -> Result<Self, OsError> {
+        let (region, ptr) = allocate::<T>(1, options)?;
+        unsafe {
+            ptr::write(ptr, value);
+        }
+        Ok(LockedBox { region, ptr })
+    }
+
+    /// Allocates locked memory, and initializes it in place from
+    /// all-bits-zero.
+    #[inline]
+    pub fn new_zeroed() -> Result<Self, OsError>
+    where
+        T: InitializableFromZeroed,
+    {
+        Self::new_zeroed_with_options(LockOptions::new())
+    }
+
+    /// Allocates locked memory with the given options, and initializes
+    /// it in place from all-bits-zero.
+    pub fn new_zeroed_with_options(options: LockOptions) -> Result<Self, OsError>
+    where
+        T: InitializableFromZeroed,
+    {
+        let (region, ptr) = allocate::<T>(1, options)?;
+        unsafe {
+            T::initialize(ptr);
+        }
+        Ok(LockedBox { region, ptr })
+    }
+}
+
+impl<T> Drop for LockedBox<T> {
+    fn drop(&mut self) {
+        unsafe {
+            ptr::drop_in_place(self.ptr);
+        }
+        self.region.wipe();
+    }
+}
+
+impl<T> Deref for LockedBox<T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        unsafe { &*self.ptr }
+    }
+}
+
+impl<T> DerefMut for LockedBox<T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.ptr }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for LockedBox<T> {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+unsafe impl<T: Send> Send for LockedBox<T> {}
+unsafe impl<T: Sync> Sync for LockedBox<T> {}
+
+/// A fixed-size slice allocated from memory locked into RAM.
+///
+/// This is the slice counterpart of `LockedBox`.
+///
+/// # Example
+///
+/// ```
+/// # use clear_on_drop::{ClearOnDrop, LockedSlice};
+/// let place: LockedSlice<u8> = LockedSlice::new_zeroed(64).unwrap();
+/// let mut buf = ClearOnDrop::new(place);
+/// buf[..5].copy_from_slice(b"hello");
+/// ```
+pub struct LockedSlice<T> {
+    region: Region,
+    ptr: *mut T,
+    len: usize,
+}
+
+impl<T> LockedSlice<T> {
+    /// Allocates locked memory for `len` elements, all set to zero.
+    #[inline]
+    pub fn new_zeroed(len: usize) -> Result<Self, OsError>
+    where
+        T: ZeroSafe,
+    {
+        Self::new_zeroed_with_options(len, LockOptions::new())
+    }
+
+    /// Allocates locked memory for `len` elements, all set to zero, with
+    /// the given options.
+    pub fn new_zeroed_with_options(len: usize, options: LockOptions) -> Result<Self, OsError>
+    where
+        T: ZeroSafe,
+    {
+        let (region, ptr) = allocate::<T>(len, options)?;
+        Ok(LockedSlice { region, ptr, len })
+    }
+
+    /// Allocates locked memory and copies `src` into it.
+    #[inline]
+    pub fn from_slice(src: &[T]) -> Result<Self, OsError>
+    where
+        T: Copy,
+    {
+        Self::from_slice_with_options(src, LockOptions::new())
+    }
+
+    /// Allocates locked memory with the given options, and copies `src`
+    /// into it.
+    pub fn from_slice_with_options(src: &[T], options: LockOptions) -> Result<Self, OsError>
+    where
+        T: Copy,
+    {
+        let (region, ptr) = allocate::<T>(src.len(), options)?;
+        unsafe {
+            ptr::copy_nonoverlapping(src.as_ptr(), ptr, src.len());
+        }
+        Ok(LockedSlice {
+            region,
+            ptr,
+            len: src.len(),
+        })
+    }
+}
+
+impl<T> Drop for LockedSlice<T> {
+    fn drop(&mut self) {
+        unsafe {
+            ptr::drop_in_place(&mut **self as *mut [T]);
+        }
+        self.region.wipe();
+    }
+}
+
+impl<T> Deref for LockedSlice<T> {
+    type Target = [T];
+
+    #[inline]
+    fn deref(&self) -> &[T] {
+        unsafe { slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl<T> DerefMut for LockedSlice<T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut [T] {
+        unsafe { slice::from_raw_parts_mut(self.ptr, self.len) }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for LockedSlice<T> {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+unsafe impl<T: Send> Send for LockedSlice<T> {}
+unsafe impl<T: Sync> Sync for LockedSlice<T> {}
+
+/// Maps and locks zeroed memory for `len` values of type `T`.
+fn allocate<T>(len: usize, options: LockOptions) -> Result<(Region, *mut T), OsError> {
+    assert!(mem::align_of::<T>() <= page_size());
+    let size = mem::size_of::<T>()
+        .checked_mul(len)
+        .expect("capacity overflow");
+    let region = options.map(size)?;
+    let ptr = region.as_ptr() as *mut T;
+    Ok((region, ptr))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LockOptions, LockedBox, LockedSlice};
+    use sys::tests::in_child;
+    use ClearOnDrop;
+
+    #[derive(Default)]
+    struct Place {
+        data: [u32; 4],
+    }
+
+    const DATA: [u32; 4] = [0x01234567, 0x89abcdef, 0xfedcba98, 0x76543210];
+
+    #[test]
+    fn locked_box() {
+        let mut place = LockedBox::new(Place { data: DATA }).unwrap();
+        assert_eq!(place.data, DATA);
+        place.data[0] = 0;
+        assert_eq!(place.data[0], 0);
+    }
+
+    #[test]
+    fn locked_box_zeroed() {
+        let place: LockedBox<Place> = LockedBox::new_zeroed().unwrap();
+        assert_eq!(place.data, [0; 4]);
+        assert_eq!(place.region.as_ptr() as usize % ::sys::page_size(), 0);
+    }
+
+    #[test]
+    fn locked_box_drops_value() {
+        let value = ::std::rc::Rc::new(());
+        let place = LockedBox::new(value.clone()).unwrap();
+        assert_eq!(::std::rc::Rc::strong_count(&value), 2);
+        drop(place);
+        assert_eq!(::std::rc::Rc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn into_clear_on_drop() {
+        let place: LockedBox<Place> = LockedBox::new_zeroed().unwrap();
+        let mut clear = ClearOnDrop::new(place);
+        clear.data = DATA;
+        let place = ClearOnDrop::into_place(clear);
+        assert_eq!(place.data, [0; 4]);
+    }
+
+    #[test]
+    fn locked_slice() {
+        let mut place = LockedSlice::from_slice(&DATA).unwrap();
+        assert_eq!(&place[..], &DATA);
+        place[0] = 0;
+        assert_eq!(place[0], 0);
+
+        let place: LockedSlice<u64> = LockedSlice::new_zeroed(1000).unwrap();
+        assert_eq!(place.len(), 1000);
+        assert!(place.iter().all(|&x| x == 0));
+    }
+
+    #[test]
+    fn empty_locked_slice() {
+        let place: LockedSlice<u8> = LockedSlice::new_zeroed(0).unwrap();
+        assert!(place.is_empty());
+    }
+
+    #[test]
+    fn wiped_in_forked_child() {
+        let place = LockedSlice::from_slice(&DATA).unwrap();
+        let options = LockOptions::new().wipe_on_fork(false);
+        let kept = LockedSlice::from_slice_with_options(&DATA, options).unwrap();
+        assert!(in_child(
+            || place.iter().all(|&x| x == 0) && kept[..] == DATA
+        ));
+        assert_eq!(&place[..], &DATA);
+    }
+
+    fn sentinel(salt: u8, i: usize) -> u8 {
+        (i as u8).wrapping_mul(37) ^ salt
+    }
+
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        haystack.windows(needle.len()).any(|w| w == needle)
+    }
+
+    // Needs core dumps written to a file in the working directory, with
+    // a core_pattern such as `core` rather than a pipe to a handler like
+    // systemd-coredump or apport, and a hard RLIMIT_CORE above zero. Run
+    // it with `cargo test excluded_from_core_dump -- --ignored`.
+    #[test]
+    #[ignore]
+    fn excluded_from_core_dump() {
+        use std::ffi::CString;
+        use std::fs;
+        use std::os::unix::ffi::OsStrExt;
+
+        use libc;
+
+        const LEN: usize = 512;
+        const SECRET: u8 = 0xa5;
+        const VISIBLE: u8 = 0x5a;
+
+        let pattern = fs::read_to_string("/proc/sys/kernel/core_pattern").unwrap_or_default();
+        assert!(
+            !pattern.starts_with('|'),
+            "core dumps are piped to {}",
+            pattern.trim()
+        );
+
+        let dir = ::std::env::temp_dir().join(format!("clear_on_drop-{}", ::std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = CString::new(dir.as_os_str().as_bytes()).unwrap();
+
+        let mut secret: LockedSlice<u8> = LockedSlice::new_zeroed(LEN).unwrap();
+        let options = LockOptions::new().exclude_from_core_dump(false);
+        let mut visible: LockedSlice<u8> =
+            LockedSlice::new_zeroed_with_options(LEN, options).unwrap();
+
+        // The patterns are only written in the child, so the parent never
+        // has a copy of them in memory which could end up in the core.
+        let pid = unsafe { libc::fork() };
+        assert!(pid >= 0);
+        if pid == 0 {
+            for i in 0..LEN {
+                secret[i] = sentinel(SECRET, i);
+                visible[i] = sentinel(VISIBLE, i);
+            }
+            unsafe {
+                let mut limit: libc::rlimit = ::core::mem::zeroed();
+                libc::getrlimit(libc::RLIMIT_CORE, &mut limit);
+                limit.rlim_cur = limit.rlim_max;
+                libc::setrlimit(libc::RLIMIT_CORE, &limit);
+                libc::chdir(path.as_ptr());
+                libc::abort();
+            }
+        }
+
+        let mut status = 0;
+        assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+        assert!(libc::WIFSIGNALED(status));
+        let core = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .find(|path| {
+                path.file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .starts_with("core")
+            });
+        let core = match core {
+            Some(ref core) if libc::WCOREDUMP(status) => Some(fs::read(core).unwrap()),
+            _ => None,
+        };
+        fs::remove_dir_all(&dir).unwrap();
+        let core = core.expect("no core dump was produced");
+
+        let secret: Vec<u8> = (0..LEN).map(|i| sentinel(SECRET, i)).collect();
+        let visible: Vec<u8> = (0..LEN).map(|i| sentinel(VISIBLE, i)).collect();
+        assert!(contains(&core, &visible));
+        assert!(!contains(&core, &secret));
+    }
+}
@@ -0,0 +1,257 @@
+use core::fmt;
+use core::mem;
+use core::ops::{Deref, DerefMut};
+use core::ptr;
+#[cfg(target_os = "linux")]
+use core::sync::atomic::{AtomicBool, Ordering};
+
+#[cfg(target_os = "linux")]
+use libc;
+
+use clear::InitializableFromZeroed;
+use locked::LockOptions;
+use sys::{page_size, OsError, Region};
+
+/// The kind of memory a `SecretMemBox` was allocated from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SecretMemMode {
+    /// Memory from `memfd_secret`, which is locked into RAM and removed
+    /// from the kernel direct map, so not even the kernel can easily
+    /// read it.
+    Secret,
+    /// Anonymous memory locked with `mlock` and excluded from core
+    /// dumps, used when `memfd_secret` is not available.
+    Locked,
+}
+
+/// A box which allocates from memory hidden from the kernel when
+/// possible.
+///
+/// On Linux 5.14 and newer, when secret memory is enabled, the value is
+/// placed in pages from `memfd_secret`, which are removed from the
+/// kernel direct map. Otherwise, it falls back to the same locked pages
+/// as a `LockedBox`; `mode` tells which kind of memory is in use. Either
+/// way, children created by `fork` see the pages as zeroed memory. When
+/// dropped, the value is dropped, and the pages are cleared and
+/// unmapped. The `LockOptions` of the locked pages can be set with
+/// `new_with_options`.
+///
+/// Like a `LockedBox`, it can be used as the place of a `ClearOnDrop`.
+///
+/// # Example
+///
+/// ```
+/// # use clear_on_drop::{ClearOnDrop, SecretMemBox};
+/// let place: SecretMemBox<[u8; 32]> = SecretMemBox::new_zeroed().unwrap();
+/// println!("using {:?} memory", place.mode());
+/// let mut key = ClearOnDrop::new(place);
+/// key.copy_from_slice(&[0x41; 32]);
+/// ```
+pub struct SecretMemBox<T> {
+    region: Region,
+    ptr: *mut T,
+    mode: SecretMemMode,
+}
+
+impl<T> SecretMemBox<T> {
+    /// Moves `value` into newly allocated secret memory.
+    #[inline]
+    pub fn new(value: T) -> Result<Self, OsError> {
+        Self::new_with_options(value, LockOptions::new())
+    }
+
+    /// Moves `value` into newly allocated secret memory, with the given
+    /// options for the locked memory used when `memfd_secret` is not
+    /// available.
+    ///
+    /// Memory from `memfd_secret` is always locked, left out of core
+    /// dumps and wiped in forked children, whatever the options.
+    pub fn new_with_options(value: T, options: LockOptions) -> Result<Self, OsError> {
+        let (region, mode) = allocate::<T>(options)?;
+        let ptr = region.as_ptr() as *mut T;
+        unsafe {
+            ptr::write(ptr, value);
+        }
+        Ok(SecretMemBox { region, ptr, mode })
+    }
+
+    /// Allocates secret memory, and initializes it in place from
+    /// all-bits-zero.
+    #[inline]
+    pub fn new_zeroed() -> Result<Self, OsError>
+    where
+        T: InitializableFromZeroed,
+    {
+        Self::new_zeroed_with_options(LockOptions::new())
+    }
+
+    /// Allocates secret memory with the given options for the locked
+    /// memory used when `memfd_secret` is not available, and initializes
+    /// it in place from all-bits-zero.
+    pub fn new_zeroed_with_options(options: LockOptions) -> Result<Self, OsError>
+    where
+        T: InitializableFromZeroed,
+    {
+        let (region, mode) = allocate::<T>(options)?;
+        let ptr = region.as_ptr() as *mut T;
+        unsafe {
+            T::initialize(ptr);
+        }
+        Ok(SecretMemBox { region, ptr, mode })
+    }
+
+    /// Returns the kind of memory the value lives in.
+    #[inline]
+    pub fn mode(&self) -> SecretMemMode {
+        self.mode
+    }
+}
+
+impl<T> Drop for SecretMemBox<T> {
+    fn drop(&mut self) {
+        unsafe {
+            ptr::drop_in_place(self.ptr);
+        }
+        self.region.wipe();
+    }
+}
+
+impl<T> Deref for SecretMemBox<T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        unsafe { &*self.ptr }
+    }
+}
+
+impl<T> DerefMut for SecretMemBox<T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.ptr }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for SecretMemBox<T> {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+unsafe impl<T: Send> Send for SecretMemBox<T> {}
+unsafe impl<T: Sync> Sync for SecretMemBox<T> {}
+
+/// Maps memory for a `T`, from `memfd_secret` if it is available, or
+/// else locked anonymous memory with the given `options`.
+fn allocate<T>(options: LockOptions) -> Result<(Region, SecretMemMode), OsError> {
+    assert!(mem::align_of::<T>() <= page_size());
+    let size = mem::size_of::<T>();
+
+    #[cfg(target_os = "linux")]
+    {
+        // Set once memfd_secret has failed because the kernel does not
+        // support it, to avoid retrying it on every allocation.
+        static UNAVAILABLE: AtomicBool = AtomicBool::new(false);
+
+        if !UNAVAILABLE.load(Ordering::Relaxed) {
+            match Region::map_secret(size) {
+                Ok(mut region) => {
+                    // The mapping is shared, so a forked child would see
+                    // the secret, and could even overwrite it. If that
+                    // cannot be prevented, the region is dropped, and
+                    // locked memory is used instead.
+                    if region.wipe_on_fork().is_ok() {
+                        return Ok((region, SecretMemMode::Secret));
+                    }
+                }
+                // Too old a kernel, or secret memory is not enabled.
+                Err(ref err)
+                    if err.call() == "memfd_secret"
+                        && matches!(err.errno(), libc::ENOSYS | libc::EINVAL | libc::ENOTSUP) =>
+                {
+                    UNAVAILABLE.store(true, Ordering::Relaxed);
+                }
+                // Other failures, such as running out of file descriptors
+                // or exceeding `RLIMIT_MEMLOCK` when mapping, may not
+                // happen next time, and locked memory may still work.
+                Err(_) => {}
+            }
+        }
+    }
+
+    let region = options.map(size)?;
+    Ok((region, SecretMemMode::Locked))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SecretMemBox, SecretMemMode};
+    use locked::{LockOptions, LockPolicy};
+    use sys::tests::in_child;
+    use ClearOnDrop;
+
+    #[derive(Default)]
+    struct Place {
+        data: [u32; 4],
+    }
+
+    const DATA: [u32; 4] = [0x01234567, 0x89abcdef, 0xfedcba98, 0x76543210];
+
+    #[test]
+    fn secret_mem_box() {
+        let mut place = SecretMemBox::new(Place { data: DATA }).unwrap();
+        assert_eq!(place.data, DATA);
+        place.data[0] = 0;
+        assert_eq!(place.data[0], 0);
+    }
+
+    #[test]
+    fn same_mode() {
+        let first: SecretMemBox<Place> = SecretMemBox::new_zeroed().unwrap();
+        let second: SecretMemBox<Place> = SecretMemBox::new_zeroed().unwrap();
+        assert_eq!(first.mode(), second.mode());
+        if cfg!(target_os = "android") {
+            assert_eq!(first.mode(), SecretMemMode::Locked);
+        }
+    }
+
+    #[test]
+    fn with_options() {
+        let options = LockOptions::new().lock_policy(LockPolicy::Fallback);
+        let place = SecretMemBox::new_with_options(Place { data: DATA }, options).unwrap();
+        assert_eq!(place.data, DATA);
+        let place: SecretMemBox<Place> = SecretMemBox::new_zeroed_with_options(options).unwrap();
+        assert_eq!(place.data, [0; 4]);
+    }
+
+    #[test]
+    fn secret_mem_box_drops_value() {
+        let value = ::std::rc::Rc::new(());
+        let place = SecretMemBox::new(value.clone()).unwrap();
+        assert_eq!(::std::rc::Rc::strong_count(&value), 2);
+        drop(place);
+        assert_eq!(::std::rc::Rc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn into_clear_on_drop() {
+        let place: SecretMemBox<Place> = SecretMemBox::new_zeroed().unwrap();
+        let mut clear = ClearOnDrop::new(place);
+        clear.data = DATA;
+        let place = ClearOnDrop::into_place(clear);
+        assert_eq!(place.data, [0; 4]);
+    }
+
+    #[test]
+    fn wiped_in_forked_child() {
+        let place = SecretMemBox::new(Place { data: DATA }).unwrap();
+        let ptr = &*place as *const Place as *mut Place;
+        assert!(in_child(|| unsafe {
+            let wiped = (*ptr).data == [0; 4];
+            (*ptr).data = [0x41414141; 4];
+            wiped
+        }));
+        assert_eq!(place.data, DATA);
+    }
+}
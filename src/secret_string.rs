@@ -0,0 +1,396 @@
+use core::borrow::{Borrow, BorrowMut};
+use core::fmt;
+use core::ops::{Deref, DerefMut};
+use core::str;
+
+use alloc::string::String;
+
+use clear::{Clear, DeepClear};
+use secret_vec::SecretVec;
+
+/// A growable string which does not leave copies of its contents behind.
+///
+/// This is the string counterpart of `SecretVec`: growing or shrinking
+/// the string moves its contents to a new allocation and clears the old
+/// one, removed characters are cleared, and the whole capacity is
+/// cleared when the string is dropped.
+///
+/// # Example
+///
+/// ```
+/// # use clear_on_drop::SecretString;
+/// let mut password = SecretString::with_capacity(8);
+/// password.push_str("hunter");
+/// password.push('2');
+/// assert_eq!(password.pop(), Some('2'));
+/// assert_eq!(&password[..], "hunter");
+/// ```
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct SecretString {
+    vec: SecretVec<u8>,
+}
+
+impl SecretString {
+    /// Creates a new, empty `SecretString`.
+    #[inline]
+    pub fn new() -> Self {
+        SecretString {
+            vec: SecretVec::new(),
+        }
+    }
+
+    /// Creates a new, empty `SecretString` with room for `capacity` bytes.
+    #[inline]
+    pub fn with_capacity(capacity: usize) -> Self {
+        SecretString {
+            vec: SecretVec::with_capacity(capacity),
+        }
+    }
+
+    /// Returns the number of bytes the string can hold without moving
+    /// to a new allocation.
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.vec.capacity()
+    }
+
+    /// Extracts a string slice containing the whole string.
+    #[inline]
+    pub fn as_str(&self) -> &str {
+        self
+    }
+
+    /// Extracts a mutable string slice containing the whole string.
+    #[inline]
+    pub fn as_mut_str(&mut self) -> &mut str {
+        self
+    }
+
+    /// Reserves capacity for at least `additional` more bytes.
+    ///
+    /// If the string has to grow, its contents are moved to a new
+    /// allocation, and the old allocation is cleared.
+    #[inline]
+    pub fn reserve(&mut self, additional: usize) {
+        self.vec.reserve(additional)
+    }
+
+    /// Reserves capacity for exactly `additional` more bytes.
+    ///
+    /// If the string has to grow, its contents are moved to a new
+    /// allocation, and the old allocation is cleared.
+    #[inline]
+    pub fn reserve_exact(&mut self, additional: usize) {
+        self.vec.reserve_exact(additional)
+    }
+
+    /// Shrinks the capacity to the length of the string.
+    ///
+    /// The contents are moved to a new allocation, and the old
+    /// allocation is cleared.
+    #[inline]
+    pub fn shrink_to_fit(&mut self) {
+        self.vec.shrink_to_fit()
+    }
+
+    /// Appends a character to the end of the string.
+    #[inline]
+    pub fn push(&mut self, ch: char) {
+        let mut buf = [0; 4];
+        self.push_str(ch.encode_utf8(&mut buf));
+        buf.clear();
+    }
+
+    /// Appends a string slice to the end of the string.
+    #[inline]
+    pub fn push_str(&mut self, string: &str) {
+        self.vec.extend_from_slice(string.as_bytes())
+    }
+
+    /// Removes the last character of the string and returns it,
+    /// clearing the bytes it occupied.
+    #[inline]
+    pub fn pop(&mut self) -> Option<char> {
+        let ch = self.chars().next_back()?;
+        let len = self.len() - ch.len_utf8();
+        self.vec.truncate(len);
+        Some(ch)
+    }
+
+    /// Inserts a character at byte position `index`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is larger than the length of the string, or if
+    /// it does not lie on a `char` boundary.
+    #[inline]
+    pub fn insert(&mut self, index: usize, ch: char) {
+        let mut buf = [0; 4];
+        self.insert_str(index, ch.encode_utf8(&mut buf));
+        buf.clear();
+    }
+
+    /// Inserts a string slice at byte position `index`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is larger than the length of the string, or if
+    /// it does not lie on a `char` boundary.
+    #[inline]
+    pub fn insert_str(&mut self, index: usize, string: &str) {
+        assert!(self.is_char_boundary(index));
+        self.vec.insert_from_slice(index, string.as_bytes())
+    }
+
+    /// Removes the character at byte position `index` and returns it,
+    /// clearing the bytes which were left unused at the end.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is not smaller than the length of the string,
+    /// or if it does not lie on a `char` boundary.
+    pub fn remove(&mut self, index: usize) -> char {
+        let ch = self[index..]
+            .chars()
+            .next()
+            .expect("cannot remove a char from the end of a string");
+        let next = index + ch.len_utf8();
+        let len = self.len();
+        self.vec.copy_within(next..len, index);
+        self.vec.truncate(len - ch.len_utf8());
+        ch
+    }
+
+    /// Shortens the string to `len` bytes, clearing the rest. Has no
+    /// effect if the string is already shorter.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `len` does not lie on a `char` boundary.
+    #[inline]
+    pub fn truncate(&mut self, len: usize) {
+        if len < self.len() {
+            assert!(self.is_char_boundary(len));
+            self.vec.truncate(len)
+        }
+    }
+
+    /// Clears the contents of the string, keeping the capacity.
+    #[inline]
+    pub fn clear(&mut self) {
+        self.vec.clear()
+    }
+}
+
+impl DeepClear for SecretString {
+    #[inline]
+    fn deep_clear(&mut self) {
+        // All-zeros is valid UTF-8.
+        self.vec.deep_clear()
+    }
+}
+
+impl fmt::Debug for SecretString {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl Deref for SecretString {
+    type Target = str;
+
+    #[inline]
+    fn deref(&self) -> &str {
+        unsafe { str::from_utf8_unchecked(&self.vec) }
+    }
+}
+
+impl DerefMut for SecretString {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut str {
+        unsafe { str::from_utf8_unchecked_mut(&mut self.vec) }
+    }
+}
+
+impl<'a> From<&'a str> for SecretString {
+    #[inline]
+    fn from(string: &'a str) -> Self {
+        let mut secret = SecretString::with_capacity(string.len());
+        secret.push_str(string);
+        secret
+    }
+}
+
+impl<'a> From<&'a mut String> for SecretString {
+    /// Copies the contents of a `String`, clears its whole capacity, and
+    /// leaves it empty.
+    ///
+    /// Earlier allocations of the `String`, if it has grown, cannot be
+    /// cleared.
+    #[inline]
+    fn from(string: &'a mut String) -> Self {
+        let secret = SecretString::from(&string[..]);
+        string.deep_clear();
+        string.clear();
+        secret
+    }
+}
+
+impl From<String> for SecretString {
+    /// Copies the contents of a `String`, and clears its whole capacity
+    /// before freeing it.
+    ///
+    /// Earlier allocations of the `String`, if it has grown, cannot be
+    /// cleared.
+    #[inline]
+    fn from(mut string: String) -> Self {
+        SecretString::from(&mut string)
+    }
+}
+
+impl<'a> Extend<&'a str> for SecretString {
+    fn extend<I: IntoIterator<Item = &'a str>>(&mut self, iter: I) {
+        for string in iter {
+            self.push_str(string);
+        }
+    }
+}
+
+impl Extend<char> for SecretString {
+    fn extend<I: IntoIterator<Item = char>>(&mut self, iter: I) {
+        let iter = iter.into_iter();
+        self.reserve(iter.size_hint().0);
+        for ch in iter {
+            self.push(ch);
+        }
+    }
+}
+
+impl AsRef<str> for SecretString {
+    #[inline]
+    fn as_ref(&self) -> &str {
+        self
+    }
+}
+
+impl AsRef<[u8]> for SecretString {
+    #[inline]
+    fn as_ref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+impl AsMut<str> for SecretString {
+    #[inline]
+    fn as_mut(&mut self) -> &mut str {
+        self
+    }
+}
+
+impl Borrow<str> for SecretString {
+    #[inline]
+    fn borrow(&self) -> &str {
+        self
+    }
+}
+
+impl BorrowMut<str> for SecretString {
+    #[inline]
+    fn borrow_mut(&mut self) -> &mut str {
+        self
+    }
+}
+
+impl PartialEq<str> for SecretString {
+    #[inline]
+    fn eq(&self, other: &str) -> bool {
+        PartialEq::eq(&**self, other)
+    }
+}
+
+impl<'a> PartialEq<&'a str> for SecretString {
+    #[inline]
+    fn eq(&self, other: &&'a str) -> bool {
+        PartialEq::eq(&**self, *other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SecretString;
+
+    use core::slice;
+
+    // Reads memory which has been cleared, but not yet freed.
+    fn spare_is_zero(string: &SecretString) -> bool {
+        let bytes = unsafe { slice::from_raw_parts(string.as_ptr(), string.capacity()) };
+        bytes[string.len()..].iter().all(|&b| b == 0)
+    }
+
+    #[test]
+    fn push() {
+        let mut string = SecretString::new();
+        string.push_str("caf");
+        string.push('é');
+        assert_eq!(string, "café");
+    }
+
+    #[test]
+    fn pop() {
+        let mut string = SecretString::from("café");
+        assert_eq!(string.pop(), Some('é'));
+        assert_eq!(string, "caf");
+        assert!(spare_is_zero(&string));
+    }
+
+    #[test]
+    fn insert_and_remove() {
+        let mut string = SecretString::from("cafe");
+        string.insert(3, 'é');
+        string.insert_str(0, "un ");
+        assert_eq!(string, "un cafée");
+        assert_eq!(string.remove(6), 'é');
+        assert_eq!(string, "un cafe");
+        assert!(spare_is_zero(&string));
+    }
+
+    #[test]
+    fn remove_wide_char() {
+        let mut string = SecretString::from("a\u{1d11e}bc");
+        assert_eq!(string.remove(1), '\u{1d11e}');
+        assert_eq!(string, "abc");
+        assert!(spare_is_zero(&string));
+    }
+
+    #[test]
+    #[should_panic]
+    fn insert_not_on_char_boundary() {
+        let mut string = SecretString::from("é");
+        string.insert(1, 'e');
+    }
+
+    #[test]
+    fn truncate() {
+        let mut string = SecretString::from("password");
+        string.truncate(4);
+        assert_eq!(string, "pass");
+        assert!(spare_is_zero(&string));
+    }
+
+    #[test]
+    fn from_string() {
+        let source = String::from("password");
+        let string = SecretString::from(source);
+        assert_eq!(string, "password");
+        assert_eq!(string.capacity(), 8);
+
+        let mut source = String::with_capacity(32);
+        source.push_str("password");
+        let string = SecretString::from(&mut source);
+        assert_eq!(string, "password");
+        assert!(source.is_empty());
+        let buf = unsafe { ::core::slice::from_raw_parts(source.as_ptr(), source.capacity()) };
+        assert!(buf.iter().all(|&b| b == 0));
+    }
+}
@@ -0,0 +1,445 @@
+use core::borrow::{Borrow, BorrowMut};
+use core::cmp;
+use core::fmt;
+use core::iter::FromIterator;
+use core::ops::{Deref, DerefMut};
+use core::ptr;
+
+use alloc::vec::Vec;
+
+use clear::{clear_spare_capacity, DeepClear, ZeroSafe};
+
+/// A growable buffer which does not leave copies of its contents behind.
+///
+/// A `Vec<T>` which grows reallocates its buffer, and the allocator
+/// is free to return the old buffer to the system without clearing it.
+/// This type instead always moves its contents to a new allocation by
+/// itself, and clears the old allocation before freeing it. Removed
+/// elements are also cleared, and the whole capacity is cleared when
+/// the buffer is dropped.
+///
+/// Since growing always copies, reserve the needed capacity in advance
+/// when it is known.
+///
+/// # Example
+///
+/// ```
+/// # use clear_on_drop::SecretVec;
+/// let mut key = SecretVec::with_capacity(16);
+/// key.extend_from_slice(b"0123456789abcdef");
+/// key.push(b'!'); // the old 16-byte buffer is cleared
+/// assert_eq!(&key[..], b"0123456789abcdef!");
+/// ```
+pub struct SecretVec<T: ZeroSafe> {
+    buf: Vec<T>,
+}
+
+impl<T: ZeroSafe> SecretVec<T> {
+    /// Creates a new, empty `SecretVec`.
+    #[inline]
+    pub fn new() -> Self {
+        SecretVec { buf: Vec::new() }
+    }
+
+    /// Creates a new, empty `SecretVec` with room for `capacity` elements.
+    #[inline]
+    pub fn with_capacity(capacity: usize) -> Self {
+        SecretVec {
+            buf: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Returns the number of elements the buffer can hold without
+    /// moving to a new allocation.
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.buf.capacity()
+    }
+
+    /// Extracts a slice containing the whole buffer.
+    #[inline]
+    pub fn as_slice(&self) -> &[T] {
+        &self.buf
+    }
+
+    /// Extracts a mutable slice containing the whole buffer.
+    #[inline]
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        &mut self.buf
+    }
+
+    /// Reserves capacity for at least `additional` more elements.
+    ///
+    /// If the buffer has to grow, its contents are moved to a new
+    /// allocation, and the old allocation is cleared.
+    pub fn reserve(&mut self, additional: usize) {
+        let required = self.required_capacity(additional);
+        if required > self.capacity() {
+            let capacity = cmp::max(required, self.capacity().saturating_mul(2));
+            self.move_to_capacity(cmp::max(capacity, 4));
+        }
+    }
+
+    /// Reserves capacity for exactly `additional` more elements.
+    ///
+    /// If the buffer has to grow, its contents are moved to a new
+    /// allocation, and the old allocation is cleared.
+    pub fn reserve_exact(&mut self, additional: usize) {
+        let required = self.required_capacity(additional);
+        if required > self.capacity() {
+            self.move_to_capacity(required);
+        }
+    }
+
+    /// Shrinks the capacity to the length of the buffer.
+    ///
+    /// The contents are moved to a new allocation, and the old
+    /// allocation is cleared.
+    pub fn shrink_to_fit(&mut self) {
+        if self.capacity() > self.len() {
+            let len = self.len();
+            self.move_to_capacity(len);
+        }
+    }
+
+    /// Appends an element to the end of the buffer.
+    #[inline]
+    pub fn push(&mut self, value: T) {
+        self.reserve(1);
+        self.buf.push(value);
+    }
+
+    /// Removes the last element of the buffer and returns it, clearing
+    /// the place it occupied.
+    #[inline]
+    pub fn pop(&mut self) -> Option<T> {
+        let value = self.buf.pop();
+        clear_spare_capacity(&mut self.buf);
+        value
+    }
+
+    /// Inserts an element at position `index`, shifting all elements
+    /// after it to the right.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index > len`.
+    pub fn insert(&mut self, index: usize, value: T) {
+        assert!(index <= self.len(), "insertion index out of bounds");
+        self.reserve(1);
+        self.buf.insert(index, value);
+    }
+
+    /// Copies all elements of a slice into the buffer at position
+    /// `index`, shifting all elements after it to the right.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index > len`.
+    pub fn insert_from_slice(&mut self, index: usize, other: &[T])
+    where
+        T: Copy,
+    {
+        let len = self.len();
+        assert!(index <= len, "insertion index out of bounds");
+        self.reserve(other.len());
+        unsafe {
+            let ptr = self.buf.as_mut_ptr().add(index);
+            ptr::copy(ptr, ptr.add(other.len()), len - index);
+            ptr::copy_nonoverlapping(other.as_ptr(), ptr, other.len());
+            self.buf.set_len(len + other.len());
+        }
+    }
+
+    /// Removes and returns the element at position `index`, shifting all
+    /// elements after it to the left, and clearing the place which was
+    /// left unused at the end.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index >= len`.
+    pub fn remove(&mut self, index: usize) -> T {
+        let value = self.buf.remove(index);
+        clear_spare_capacity(&mut self.buf);
+        value
+    }
+
+    /// Shortens the buffer to `len` elements, dropping and clearing the
+    /// rest. Has no effect if the buffer is already shorter.
+    #[inline]
+    pub fn truncate(&mut self, len: usize) {
+        if len < self.len() {
+            self.buf.truncate(len);
+            clear_spare_capacity(&mut self.buf);
+        }
+    }
+
+    /// Removes and clears all elements, keeping the capacity.
+    #[inline]
+    pub fn clear(&mut self) {
+        self.truncate(0);
+    }
+
+    /// Clones and appends all elements of a slice to the buffer.
+    pub fn extend_from_slice(&mut self, other: &[T])
+    where
+        T: Clone,
+    {
+        self.reserve(other.len());
+        self.buf.extend_from_slice(other);
+    }
+
+    fn required_capacity(&self, additional: usize) -> usize {
+        self.len()
+            .checked_add(additional)
+            .expect("capacity overflow")
+    }
+
+    /// Moves the contents to a new allocation of the given capacity,
+    /// clearing the old allocation before freeing it.
+    fn move_to_capacity(&mut self, capacity: usize) {
+        let len = self.len();
+        debug_assert!(capacity >= len);
+        let mut buf = Vec::with_capacity(capacity);
+        unsafe {
+            ptr::copy_nonoverlapping(self.buf.as_ptr(), buf.as_mut_ptr(), len);
+            self.buf.set_len(0);
+            buf.set_len(len);
+        }
+        clear_spare_capacity(&mut self.buf);
+        self.buf = buf;
+    }
+}
+
+impl<T: ZeroSafe> Drop for SecretVec<T> {
+    #[inline]
+    fn drop(&mut self) {
+        self.buf.clear();
+        clear_spare_capacity(&mut self.buf);
+    }
+}
+
+impl<T: ZeroSafe> DeepClear for SecretVec<T> {
+    #[inline]
+    fn deep_clear(&mut self) {
+        let len = self.len();
+        unsafe {
+            // Leave the elements in place; all-bits-zero is a valid T.
+            self.buf.set_len(0);
+            clear_spare_capacity(&mut self.buf);
+            self.buf.set_len(len);
+        }
+    }
+}
+
+impl<T: ZeroSafe> Default for SecretVec<T> {
+    #[inline]
+    fn default() -> Self {
+        SecretVec::new()
+    }
+}
+
+impl<T: ZeroSafe + Clone> Clone for SecretVec<T> {
+    fn clone(&self) -> Self {
+        let mut clone = SecretVec::with_capacity(self.len());
+        clone.extend_from_slice(self);
+        clone
+    }
+}
+
+impl<T: ZeroSafe + fmt::Debug> fmt::Debug for SecretVec<T> {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: ZeroSafe> Deref for SecretVec<T> {
+    type Target = [T];
+
+    #[inline]
+    fn deref(&self) -> &[T] {
+        &self.buf
+    }
+}
+
+impl<T: ZeroSafe> DerefMut for SecretVec<T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut [T] {
+        &mut self.buf
+    }
+}
+
+impl<T: ZeroSafe> From<Vec<T>> for SecretVec<T> {
+    /// Takes ownership of the allocation of a `Vec`, without copying.
+    ///
+    /// Earlier allocations of the `Vec`, if it has grown, cannot be
+    /// cleared.
+    #[inline]
+    fn from(buf: Vec<T>) -> Self {
+        SecretVec { buf }
+    }
+}
+
+impl<'a, T: ZeroSafe + Clone> From<&'a [T]> for SecretVec<T> {
+    #[inline]
+    fn from(slice: &'a [T]) -> Self {
+        let mut vec = SecretVec::with_capacity(slice.len());
+        vec.extend_from_slice(slice);
+        vec
+    }
+}
+
+impl<T: ZeroSafe> Extend<T> for SecretVec<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        let iter = iter.into_iter();
+        self.reserve(iter.size_hint().0);
+        for value in iter {
+            self.push(value);
+        }
+    }
+}
+
+impl<'a, T: ZeroSafe + Copy + 'a> Extend<&'a T> for SecretVec<T> {
+    #[inline]
+    fn extend<I: IntoIterator<Item = &'a T>>(&mut self, iter: I) {
+        self.extend(iter.into_iter().cloned())
+    }
+}
+
+impl<T: ZeroSafe> FromIterator<T> for SecretVec<T> {
+    #[inline]
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut vec = SecretVec::new();
+        vec.extend(iter);
+        vec
+    }
+}
+
+impl<T: ZeroSafe> AsRef<[T]> for SecretVec<T> {
+    #[inline]
+    fn as_ref(&self) -> &[T] {
+        self
+    }
+}
+
+impl<T: ZeroSafe> AsMut<[T]> for SecretVec<T> {
+    #[inline]
+    fn as_mut(&mut self) -> &mut [T] {
+        self
+    }
+}
+
+impl<T: ZeroSafe> Borrow<[T]> for SecretVec<T> {
+    #[inline]
+    fn borrow(&self) -> &[T] {
+        self
+    }
+}
+
+impl<T: ZeroSafe> BorrowMut<[T]> for SecretVec<T> {
+    #[inline]
+    fn borrow_mut(&mut self) -> &mut [T] {
+        self
+    }
+}
+
+impl<T: ZeroSafe + PartialEq> PartialEq for SecretVec<T> {
+    #[inline]
+    fn eq(&self, other: &Self) -> bool {
+        PartialEq::eq(&**self, &**other)
+    }
+}
+
+impl<T: ZeroSafe + Eq> Eq for SecretVec<T> {}
+
+#[cfg(test)]
+mod tests {
+    use super::SecretVec;
+    use clear::DeepClear;
+
+    use core::slice;
+
+    // Reads memory which has been cleared, but not yet freed.
+    fn read(ptr: *const u8, len: usize) -> Vec<u8> {
+        unsafe { slice::from_raw_parts(ptr, len) }.to_vec()
+    }
+
+    fn spare_is_zero(vec: &SecretVec<u8>) -> bool {
+        let len = vec.len();
+        read(vec.as_ptr(), vec.capacity())[len..]
+            .iter()
+            .all(|&b| b == 0)
+    }
+
+    const DATA: &[u8] = b"0123456789abcdef";
+
+    #[test]
+    fn push() {
+        let mut vec = SecretVec::new();
+        for &b in DATA {
+            vec.push(b);
+        }
+        assert_eq!(&vec[..], DATA);
+        assert!(spare_is_zero(&vec));
+    }
+
+    #[test]
+    fn grow_moves_to_new_allocation() {
+        let mut vec = SecretVec::with_capacity(DATA.len());
+        vec.extend_from_slice(DATA);
+        let old = vec.as_ptr();
+        vec.reserve(1);
+        assert_ne!(vec.as_ptr(), old);
+        assert_eq!(&vec[..], DATA);
+    }
+
+    #[test]
+    fn truncate_clears_tail() {
+        let mut vec = SecretVec::from(DATA);
+        vec.truncate(4);
+        assert_eq!(&vec[..], b"0123");
+        assert!(spare_is_zero(&vec));
+    }
+
+    #[test]
+    fn pop_and_remove_clear_tail() {
+        let mut vec = SecretVec::from(DATA);
+        assert_eq!(vec.pop(), Some(b'f'));
+        assert_eq!(vec.remove(0), b'0');
+        assert_eq!(&vec[..], b"123456789abcde");
+        assert!(spare_is_zero(&vec));
+    }
+
+    #[test]
+    fn insert() {
+        let mut vec: SecretVec<u8> = DATA.iter().cloned().collect();
+        vec.insert(0, b'-');
+        vec.insert(vec.len(), b'-');
+        assert_eq!(&vec[..], b"-0123456789abcdef-");
+    }
+
+    #[test]
+    fn insert_from_slice() {
+        let mut vec = SecretVec::from(&DATA[..8]);
+        vec.insert_from_slice(4, &DATA[8..]);
+        assert_eq!(&vec[..], b"012389abcdef4567");
+    }
+
+    #[test]
+    fn shrink_to_fit() {
+        let mut vec = SecretVec::with_capacity(64);
+        vec.extend_from_slice(DATA);
+        vec.shrink_to_fit();
+        assert_eq!(vec.capacity(), DATA.len());
+        assert_eq!(&vec[..], DATA);
+    }
+
+    #[test]
+    fn deep_clear() {
+        let mut vec = SecretVec::from(DATA);
+        vec.deep_clear();
+        assert_eq!(&vec[..], &[0; 16][..]);
+        assert!(spare_is_zero(&vec));
+    }
+}
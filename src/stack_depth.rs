@@ -0,0 +1,373 @@
+use core::any::TypeId;
+use core::cmp;
+use core::mem::{self, MaybeUninit};
+use core::panic::Location;
+use core::ptr;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use clear_stack_on_return::{available_stack, clear_stack_bytes, clear_stack_on_return_bytes};
+use hide::{hide_mem, hide_ptr};
+
+/// Calls a closure, measures how much of the stack it used, and
+/// overwrites that much of its stack on return.
+///
+/// Before calling the closure, this function paints `max_bytes` of the
+/// stack below it with a canary pattern; after the closure returns, the
+/// deepest byte which no longer holds the pattern tells how deep the
+/// closure wrote. The used part, plus a small margin, is then
+/// overwritten with zeros, like `clear_stack_on_return_bytes` does.
+///
+/// Returns the result of the closure, and the number of bytes it used,
+/// which is about `max_bytes` (rounded up to 1 KiB) if the closure used
+/// that much or more. Where the bounds of the thread stack are known,
+/// no more than about three quarters of `available_stack()` is painted.
+/// The measurement is useful to choose the amount of stack to clear
+/// with `clear_stack_on_return`, or can be done automatically by
+/// `clear_stack_on_return_auto`.
+///
+/// # Example
+///
+/// ```
+/// # use clear_on_drop::clear_stack_on_return_measured;
+/// # fn encrypt(input: &[u8]) -> Vec<u8> { input.to_owned() }
+/// let input = b"abc";
+/// let (result, used) = clear_stack_on_return_measured(65536, || encrypt(input));
+/// println!("encrypt used {} bytes of stack", used);
+/// ```
+#[inline]
+pub fn clear_stack_on_return_measured<F, R>(max_bytes: usize, mut f: F) -> (R, usize)
+where
+    F: FnMut() -> R,
+{
+    // Each block is in a frame which is a little larger.
+    let max_bytes = match available_stack() {
+        Some(available) => cmp::min(max_bytes, available - available / 4),
+        None => max_bytes,
+    };
+    let blocks = (max_bytes.saturating_add(PAINT_BLOCK - 1) / PAINT_BLOCK).max(1);
+    let mut painted = Painted {
+        first: 0,
+        stride: 0,
+        blocks,
+    };
+    // Do not inline any of these, so they all use the same stack space.
+    hide_ptr::<fn(usize, &mut Painted)>(paint_stack)(0, &mut painted);
+    let mut clear = ClearUsedStack {
+        painted: &painted,
+        used: None,
+    };
+    let result = hide_ptr::<&mut dyn FnMut() -> R>(&mut f)();
+    let used = hide_ptr::<fn(&Painted) -> usize>(used_bytes)(&painted);
+    clear.used = Some(used);
+    (result, used)
+}
+
+/// Calls a closure and overwrites the part of its stack it used on
+/// return, measuring how much that is on the first call.
+///
+/// The first call from each place in the source code, with each type
+/// of closure, measures the stack used by the closure, up to 64 KiB,
+/// with `clear_stack_on_return_measured`. Later calls from the same
+/// place with the same type of closure clear that much stack plus a
+/// 1 KiB margin, without measuring it again, so the closure should use
+/// about the same amount of stack on every call, as is usually the
+/// case for cryptographic algorithms. The `TypeId` of the closure is
+/// part of the key, so the closure must be `'static`: instead of
+/// borrowing from its environment, it can be a `move` closure.
+///
+/// # Example
+///
+/// ```
+/// # use clear_on_drop::clear_stack_on_return_auto;
+/// # fn encrypt(input: &[u8]) -> Vec<u8> { input.to_owned() }
+/// for input in &[b"abc", b"def"] {
+///     let result = clear_stack_on_return_auto(move || encrypt(&input[..]));
+/// }
+/// ```
+#[inline]
+#[track_caller]
+pub fn clear_stack_on_return_auto<F, R>(f: F) -> R
+where
+    F: FnMut() -> R + 'static,
+{
+    let location = Location::caller() as *const Location as usize;
+    let closure: ClosureKey = TypeId::of::<F>;
+    match cached_depth(location, closure) {
+        Some(used) => clear_stack_on_return_bytes(used + AUTO_MARGIN, f),
+        None => {
+            let (result, used) = clear_stack_on_return_measured(AUTO_MAX_BYTES, f);
+            cache_depth(location, closure, used);
+            result
+        }
+    }
+}
+
+/// The size of the blocks painted with the canary pattern.
+const PAINT_BLOCK: usize = 1024;
+
+/// How much of the top of each block is not checked for the canary
+/// pattern.
+const PAINT_SKIP: usize = 256;
+
+/// The canary pattern.
+const CANARY: u8 = 0xa5;
+
+/// The canary pattern, in every byte of a word.
+const CANARY_WORD: usize = usize::MAX / 0xff * CANARY as usize;
+
+const WORD: usize = mem::size_of::<usize>();
+
+/// How much stack `clear_stack_on_return_auto` measures.
+const AUTO_MAX_BYTES: usize = 64 * 1024;
+
+/// How much more stack than measured `clear_stack_on_return_auto`
+/// clears.
+const AUTO_MARGIN: usize = 1024;
+
+/// Where the canary pattern was painted: in `blocks` blocks, each
+/// `stride` bytes below the previous one, starting at `first`.
+struct Painted {
+    first: usize,
+    stride: usize,
+    blocks: usize,
+}
+
+impl Painted {
+    #[inline]
+    fn block(&self, index: usize) -> usize {
+        self.first - index * self.stride
+    }
+}
+
+/// Paints the canary pattern on the stack, one block per frame.
+///
+/// Not inlined, not even into itself, so every frame has the same size.
+#[inline(never)]
+fn paint_stack(index: usize, painted: &mut Painted) {
+    let mut buf = MaybeUninit::<[usize; PAINT_BLOCK / WORD]>::uninit();
+    let ptr = buf.as_mut_ptr() as *mut usize;
+    // Volatile, since the weaker `hide_mem` backends do not always keep
+    // the writes from being removed.
+    for i in 0..PAINT_BLOCK / WORD {
+        unsafe { ptr::write_volatile(ptr.add(i), CANARY_WORD) };
+    }
+    hide_mem(&mut buf); // prevent moving after recursive call
+
+    // Every frame has the same size, so the distance between the first
+    // two gives the position of all the others.
+    match index {
+        0 => painted.first = ptr as usize,
+        1 => painted.stride = painted.first - ptr as usize,
+        _ => {}
+    }
+    if index + 1 < painted.blocks {
+        paint_stack(index + 1, painted);
+    }
+    hide_mem(&mut buf); // prevent reuse of stack space for call
+}
+
+/// Overwrites the part of the painted stack which was used when dropped,
+/// so it is also cleared when the closure panics.
+struct ClearUsedStack<'a> {
+    painted: &'a Painted,
+    used: Option<usize>,
+}
+
+impl<'a> Drop for ClearUsedStack<'a> {
+    #[inline]
+    fn drop(&mut self) {
+        let used = match self.used {
+            Some(used) => used,
+            None => hide_ptr::<fn(&Painted) -> usize>(used_bytes)(self.painted),
+        };
+        // Writes to the unchecked top of the next block might be missed.
+        hide_ptr::<fn(usize)>(clear_stack_bytes)(used + PAINT_SKIP);
+    }
+}
+
+/// Returns how far below the painted area's top the deepest byte which
+/// no longer holds the canary pattern is.
+fn used_bytes(painted: &Painted) -> usize {
+    let top = painted.first + PAINT_BLOCK;
+    for index in (0..painted.blocks).rev() {
+        let block = painted.block(index);
+        // The top of each block is overwritten by the calls `paint_stack`
+        // makes after the recursive call returns.
+        for offset in 0..PAINT_BLOCK - PAINT_SKIP {
+            let byte = unsafe { ptr::read_volatile((block + offset) as *const u8) };
+            if byte != CANARY {
+                return top - (block + offset);
+            }
+        }
+    }
+    0
+}
+
+/// A function returning the `TypeId` of a closure type. Since the
+/// compiler may emit a function more than once, two different addresses
+/// are compared by calling them.
+type ClosureKey = fn() -> TypeId;
+
+#[inline]
+fn same_closure(stored: usize, closure: ClosureKey) -> bool {
+    if stored == closure as usize {
+        return true;
+    }
+    stored != 0 && unsafe { mem::transmute::<usize, ClosureKey>(stored) }() == closure()
+}
+
+/// A measurement of `clear_stack_on_return_auto`, with the address of
+/// the caller's `Location` and the `TypeId` of the closure as the key.
+struct CallSite {
+    location: AtomicUsize,
+    closure: AtomicUsize,
+    used: AtomicUsize,
+}
+
+const CALL_SITES: usize = 64;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_CALL_SITE: CallSite = CallSite {
+    location: AtomicUsize::new(0),
+    closure: AtomicUsize::new(0),
+    used: AtomicUsize::new(0),
+};
+
+static CALL_SITE_CACHE: [CallSite; CALL_SITES] = [EMPTY_CALL_SITE; CALL_SITES];
+
+/// Returns the stack used by the `closure` called from `location`, if it
+/// was measured.
+fn cached_depth(location: usize, closure: ClosureKey) -> Option<usize> {
+    for site in CALL_SITE_CACHE.iter() {
+        match site.location.load(Ordering::Acquire) {
+            0 => return None,
+            key if key == location
+                && same_closure(site.closure.load(Ordering::Acquire), closure) =>
+            {
+                // Zero while the measurement is being stored.
+                let used = site.used.load(Ordering::Acquire);
+                return if used == 0 { None } else { Some(used - 1) };
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Records the stack used by the `closure` called from `location`. When
+/// the cache is full, the measurement is dropped, so later calls will
+/// measure again.
+fn cache_depth(location: usize, closure: ClosureKey, used: usize) {
+    for site in CALL_SITE_CACHE.iter() {
+        let claimed =
+            site.location
+                .compare_exchange(0, location, Ordering::AcqRel, Ordering::Acquire);
+        if claimed.is_ok() {
+            site.closure.store(closure as usize, Ordering::Release);
+        }
+        // A site claimed by another thread has no closure until it
+        // stores it, and is skipped meanwhile.
+        if claimed.is_ok()
+            || (claimed == Err(location)
+                && same_closure(site.closure.load(Ordering::Acquire), closure))
+        {
+            site.used.store(used + 1, Ordering::Release);
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        cache_depth, cached_depth, clear_stack_on_return_auto, clear_stack_on_return_measured,
+    };
+    use hide::hide_mem;
+
+    use core::any::TypeId;
+
+    use core::ptr;
+    use std::panic::{self, AssertUnwindSafe};
+
+    #[inline(never)]
+    fn use_stack(bytes: usize) -> u8 {
+        let mut buf = [0x41u8; 16384];
+        hide_mem(&mut buf);
+        buf[16384 - bytes]
+    }
+
+    #[test]
+    fn measures_usage() {
+        let (result, small) = clear_stack_on_return_measured(65536, || use_stack(1));
+        assert_eq!(result, 0x41);
+        // The frames of the closure and of `use_stack` add a little.
+        assert!(small >= 16384 - 256, "{}", small);
+        assert!(small < 16384 + 4096, "{}", small);
+
+        let (_, tiny) = clear_stack_on_return_measured(65536, || 0x41);
+        assert!(tiny < 1024, "{}", tiny);
+    }
+
+    #[inline(never)]
+    fn dirty_stack_and_panic(addr: &mut usize) {
+        let mut buf = [0x41u8; 8192];
+        hide_mem(&mut buf);
+        *addr = &buf as *const _ as usize;
+        panic!("dirty stack");
+    }
+
+    #[test]
+    fn clears_on_panic() {
+        let mut buf = 0;
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            clear_stack_on_return_measured(65536, || dirty_stack_and_panic(&mut buf))
+        }));
+        assert!(result.is_err());
+        let data = unsafe { ptr::read_volatile(buf as *const [u8; 8192]) };
+        assert!(!data.windows(16).any(|w| w.iter().all(|&b| b == 0x41)));
+    }
+
+    #[test]
+    fn saturates_at_max_bytes() {
+        let (_, used) = clear_stack_on_return_measured(4096, || use_stack(1));
+        assert!(used <= 4096 + 1024, "{}", used);
+        assert!(used >= 4096 - 1024, "{}", used);
+    }
+
+    #[test]
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn bounded_by_thread_stack() {
+        let small = ::std::thread::Builder::new().stack_size(128 * 1024);
+        let handle = small
+            .spawn(|| clear_stack_on_return_measured(1 << 30, || 0x41))
+            .unwrap();
+        let (result, used) = handle.join().unwrap();
+        assert_eq!(result, 0x41);
+        assert!(used < 128 * 1024, "{}", used);
+    }
+
+    #[test]
+    fn cache() {
+        static KEY: u8 = 0;
+        let location = &KEY as *const u8 as usize;
+        let closure = TypeId::of::<u8>;
+        let other = TypeId::of::<u16>;
+        assert_eq!(cached_depth(location, closure), None);
+        cache_depth(location, closure, 100);
+        assert_eq!(cached_depth(location, closure), Some(100));
+        cache_depth(location, closure, 200);
+        assert_eq!(cached_depth(location, closure), Some(200));
+
+        assert_eq!(cached_depth(location, other), None);
+        cache_depth(location, other, 300);
+        assert_eq!(cached_depth(location, other), Some(300));
+        assert_eq!(cached_depth(location, closure), Some(200));
+    }
+
+    #[test]
+    fn auto() {
+        for _ in 0..3 {
+            assert_eq!(clear_stack_on_return_auto(|| use_stack(1)), 0x41);
+        }
+    }
+}
@@ -0,0 +1,725 @@
+//! Page-granular memory mappings from the operating system.
+//!
+//! Secret memory which has to be treated specially by the kernel (for
+//! instance, locked into RAM) is allocated directly with `mmap`, in
+//! whole pages, so the special treatment does not leak onto unrelated
+//! data sharing the same pages.
+
+use core::fmt;
+use core::hint;
+use core::mem;
+use core::ptr::{self, NonNull};
+use core::slice;
+use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+use libc;
+
+use budget::{self, Residency};
+use clear::Clear;
+
+/// An error returned by a system call.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OsError {
+    call: &'static str,
+    errno: i32,
+}
+
+impl OsError {
+    /// Creates an error for a failed `call` from the current `errno`.
+    pub(crate) fn last(call: &'static str) -> Self {
+        OsError {
+            call,
+            errno: errno(),
+        }
+    }
+
+    /// Returns the name of the system call which failed.
+    #[inline]
+    pub fn call(&self) -> &'static str {
+        self.call
+    }
+
+    /// Returns the `errno` value the system call failed with.
+    #[inline]
+    pub fn errno(&self) -> i32 {
+        self.errno
+    }
+}
+
+impl fmt::Display for OsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} failed with errno {}", self.call, self.errno)
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn errno() -> i32 {
+    unsafe { *libc::__errno_location() }
+}
+
+#[cfg(target_os = "android")]
+fn errno() -> i32 {
+    unsafe { *libc::__errno() }
+}
+
+pub(crate) use clear_stack_on_return::page_size;
+
+/// Rounds `len` up to a whole number of pages, with at least one page.
+pub(crate) fn round_to_pages(len: usize) -> usize {
+    let page_size = page_size();
+    let pages = len.checked_add(page_size - 1).expect("capacity overflow") / page_size;
+    if pages == 0 {
+        page_size
+    } else {
+        pages * page_size
+    }
+}
+
+/// Excludes the pages containing `data` from core dumps.
+///
+/// Whole pages are excluded, so other data sharing the first or last
+/// page with `data` will be excluded too. The pages are not restored
+/// when `data` is freed, and may be reused later for other data.
+///
+/// # Example
+///
+/// ```
+/// # use clear_on_drop::exclude_from_core_dump;
+/// let mut key = vec![0u8; 32];
+/// exclude_from_core_dump(&mut key).unwrap();
+/// ```
+pub fn exclude_from_core_dump(data: &mut [u8]) -> Result<(), OsError> {
+    if data.is_empty() {
+        return Ok(());
+    }
+    let page_size = page_size();
+    let start = data.as_ptr() as usize & !(page_size - 1);
+    let end = data.as_ptr() as usize + data.len();
+    madvise(start as *mut u8, end - start, libc::MADV_DONTDUMP)
+}
+
+fn madvise(ptr: *mut u8, len: usize, advice: libc::c_int) -> Result<(), OsError> {
+    if unsafe { libc::madvise(ptr as *mut _, len, advice) } != 0 {
+        return Err(OsError::last("madvise"));
+    }
+    Ok(())
+}
+
+/// Returns the lowest and highest addresses of the current thread's
+/// stack, leaving out its guard area.
+///
+/// Looking them up is slow (for the main thread, the C library has to
+/// read `/proc/self/maps`), so they are kept in thread-specific data
+/// after the first call on each thread.
+pub(crate) fn stack_bounds() -> Option<(usize, usize)> {
+    let keys = stack_bounds_keys();
+    if let Some((low_key, high_key)) = keys {
+        let low = unsafe { libc::pthread_getspecific(low_key) } as usize;
+        if low != 0 {
+            let high = unsafe { libc::pthread_getspecific(high_key) } as usize;
+            return Some((low, high));
+        }
+    }
+    let (low, high) = thread_stack_bounds()?;
+    if let Some((low_key, high_key)) = keys {
+        // The low address last, since it tells whether both are set.
+        unsafe {
+            if libc::pthread_setspecific(high_key, high as *const _) == 0 {
+                libc::pthread_setspecific(low_key, low as *const _);
+            }
+        }
+    }
+    Some((low, high))
+}
+
+/// Returns the keys of the thread-specific data holding the bounds of
+/// each thread's stack, creating them once, or `None` if they cannot
+/// be created.
+fn stack_bounds_keys() -> Option<(libc::pthread_key_t, libc::pthread_key_t)> {
+    const NONE: usize = 0;
+    const CREATING: usize = 1;
+    const CREATED: usize = 2;
+    const FAILED: usize = 3;
+    static STATE: AtomicUsize = AtomicUsize::new(NONE);
+    static LOW_KEY: AtomicUsize = AtomicUsize::new(0);
+    static HIGH_KEY: AtomicUsize = AtomicUsize::new(0);
+
+    loop {
+        match STATE.compare_exchange(NONE, CREATING, Ordering::Acquire, Ordering::Acquire) {
+            Ok(_) => {
+                let mut low_key = 0;
+                let mut high_key = 0;
+                unsafe {
+                    if libc::pthread_key_create(&mut low_key, None) != 0 {
+                        STATE.store(FAILED, Ordering::Release);
+                        return None;
+                    }
+                    if libc::pthread_key_create(&mut high_key, None) != 0 {
+                        libc::pthread_key_delete(low_key);
+                        STATE.store(FAILED, Ordering::Release);
+                        return None;
+                    }
+                }
+                LOW_KEY.store(low_key as usize, Ordering::Relaxed);
+                HIGH_KEY.store(high_key as usize, Ordering::Relaxed);
+                STATE.store(CREATED, Ordering::Release);
+            }
+            Err(CREATED) => {}
+            Err(FAILED) => return None,
+            Err(_) => {
+                hint::spin_loop();
+                continue;
+            }
+        }
+        return Some((
+            LOW_KEY.load(Ordering::Relaxed) as libc::pthread_key_t,
+            HIGH_KEY.load(Ordering::Relaxed) as libc::pthread_key_t,
+        ));
+    }
+}
+
+fn thread_stack_bounds() -> Option<(usize, usize)> {
+    unsafe {
+        let mut attr: libc::pthread_attr_t = core::mem::zeroed();
+        if libc::pthread_getattr_np(libc::pthread_self(), &mut attr) != 0 {
+            return None;
+        }
+        let mut addr = ptr::null_mut();
+        let mut size = 0;
+        let mut guard = 0;
+        let ok = libc::pthread_attr_getstack(&attr, &mut addr, &mut size) == 0
+            && libc::pthread_attr_getguardsize(&attr, &mut guard) == 0;
+        libc::pthread_attr_destroy(&mut attr);
+        if !ok {
+            return None;
+        }
+        // Older versions of glibc count the guard area as part of the
+        // stack; newer ones do not, and then this wastes a little.
+        let low = addr as usize;
+        Some((low + guard, low + size))
+    }
+}
+
+/// Reads a small file, such as one from `/proc`, into `buf`, and returns
+/// the part of `buf` which was filled. `path` must end with a NUL byte.
+pub(crate) fn read_file<'a>(path: &[u8], buf: &'a mut [u8]) -> Option<&'a [u8]> {
+    debug_assert_eq!(path.last(), Some(&0));
+    let fd = unsafe { libc::open(path.as_ptr() as *const _, libc::O_RDONLY | libc::O_CLOEXEC) };
+    if fd < 0 {
+        return None;
+    }
+    let mut len = 0;
+    while len < buf.len() {
+        let ret = unsafe { libc::read(fd, buf[len..].as_mut_ptr() as *mut _, buf.len() - len) };
+        if ret <= 0 {
+            break;
+        }
+        len += ret as usize;
+    }
+    unsafe {
+        libc::close(fd);
+    }
+    Some(&buf[..len])
+}
+
+/// Parses the decimal number at the start of `text`, after any spaces
+/// or tabs.
+pub(crate) fn parse_decimal(text: &[u8]) -> Option<usize> {
+    let mut digits = text
+        .iter()
+        .skip_while(|&&b| b == b' ' || b == b'\t')
+        .take_while(|b| b.is_ascii_digit())
+        .peekable();
+    digits.peek()?;
+    let mut value: usize = 0;
+    for digit in digits {
+        value = value
+            .checked_mul(10)?
+            .checked_add((digit - b'0') as usize)?;
+    }
+    Some(value)
+}
+
+/// Mappings which have to be replaced by zeroed memory in forked
+/// children, as `(address, length)` pairs; an address of zero marks a
+/// free slot.
+///
+/// This is the fallback for kernels without `MADV_WIPEONFORK`, and for
+/// shared mappings, which the child would otherwise share with the
+/// parent.
+struct ForkSlot {
+    addr: AtomicUsize,
+    len: AtomicUsize,
+}
+
+const FORK_SLOTS: usize = 256;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_FORK_SLOT: ForkSlot = ForkSlot {
+    addr: AtomicUsize::new(0),
+    len: AtomicUsize::new(0),
+};
+
+/// A block of slots. When all of them are taken, another block is mapped
+/// and linked after it; blocks are never unmapped, and all-bits-zero is
+/// an empty block.
+struct ForkSlots {
+    slots: [ForkSlot; FORK_SLOTS],
+    next: AtomicPtr<ForkSlots>,
+}
+
+impl ForkSlots {
+    /// Returns the block linked after this one, if any.
+    #[inline]
+    fn next(&self) -> Option<&'static ForkSlots> {
+        unsafe { self.next.load(Ordering::Acquire).as_ref() }
+    }
+}
+
+static FORK_REGISTRY: ForkSlots = ForkSlots {
+    slots: [EMPTY_FORK_SLOT; FORK_SLOTS],
+    next: AtomicPtr::new(ptr::null_mut()),
+};
+
+/// Runs in the child after a `fork`, where only async-signal-safe calls
+/// are allowed.
+unsafe extern "C" fn wipe_registered_in_child() {
+    let mut block = Some(&FORK_REGISTRY);
+    while let Some(slots) = block {
+        for slot in slots.slots.iter() {
+            let addr = slot.addr.load(Ordering::Acquire);
+            let len = slot.len.load(Ordering::Acquire);
+            if addr != 0 && len != 0 {
+                libc::mmap(
+                    addr as *mut _,
+                    len,
+                    libc::PROT_READ | libc::PROT_WRITE,
+                    libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_FIXED,
+                    -1,
+                    0,
+                );
+            }
+        }
+        block = slots.next();
+    }
+}
+
+/// Installs `wipe_registered_in_child` with `pthread_atfork`, once.
+fn install_fork_handler() -> Result<(), OsError> {
+    const NONE: usize = 0;
+    const INSTALLING: usize = 1;
+    const INSTALLED: usize = 2;
+    static STATE: AtomicUsize = AtomicUsize::new(NONE);
+
+    loop {
+        match STATE.compare_exchange(NONE, INSTALLING, Ordering::Acquire, Ordering::Acquire) {
+            Ok(_) => {
+                let ret =
+                    unsafe { libc::pthread_atfork(None, None, Some(wipe_registered_in_child)) };
+                if ret != 0 {
+                    STATE.store(NONE, Ordering::Release);
+                    return Err(OsError {
+                        call: "pthread_atfork",
+                        errno: ret,
+                    });
+                }
+                STATE.store(INSTALLED, Ordering::Release);
+                return Ok(());
+            }
+            Err(INSTALLED) => return Ok(()),
+            Err(_) => hint::spin_loop(),
+        }
+    }
+}
+
+/// Registers a mapping to be replaced by zeroed memory in forked
+/// children, returning its slot.
+fn register_for_fork(ptr: *mut u8, len: usize) -> Result<&'static ForkSlot, OsError> {
+    install_fork_handler()?;
+    let mut slots = &FORK_REGISTRY;
+    loop {
+        for slot in slots.slots.iter() {
+            let claimed =
+                slot.addr
+                    .compare_exchange(0, ptr as usize, Ordering::AcqRel, Ordering::Relaxed);
+            if claimed.is_ok() {
+                slot.len.store(len, Ordering::Release);
+                return Ok(slot);
+            }
+        }
+        slots = match slots.next() {
+            Some(next) => next,
+            None => add_fork_slots(slots)?,
+        };
+    }
+}
+
+/// Maps a new block of slots and links it after `last`, or returns the
+/// block another thread linked there first.
+#[cold]
+fn add_fork_slots(last: &ForkSlots) -> Result<&'static ForkSlots, OsError> {
+    let size = mem::size_of::<ForkSlots>();
+    let new = unsafe {
+        libc::mmap(
+            ptr::null_mut(),
+            size,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+            -1,
+            0,
+        )
+    };
+    if new == libc::MAP_FAILED {
+        return Err(OsError::last("mmap"));
+    }
+    let new = new as *mut ForkSlots;
+    match last
+        .next
+        .compare_exchange(ptr::null_mut(), new, Ordering::AcqRel, Ordering::Acquire)
+    {
+        Ok(_) => Ok(unsafe { &*new }),
+        Err(other) => {
+            unsafe {
+                libc::munmap(new as *mut _, size);
+            }
+            Ok(unsafe { &*other })
+        }
+    }
+}
+
+/// Undoes `register_for_fork`.
+fn unregister_for_fork(slot: &ForkSlot) {
+    slot.len.store(0, Ordering::Release);
+    slot.addr.store(0, Ordering::Release);
+}
+
+/// The number of the `memfd_secret` system call, which `libc` does not
+/// define for every target. Since Linux 5.1, new system calls have the
+/// same number on every architecture, offset by the ABI base on MIPS
+/// and x32. Where the kernel does not provide it, the call fails with
+/// `ENOSYS`, and the caller falls back to locked memory.
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "mips", target_arch = "mips32r6")
+))]
+const SYS_MEMFD_SECRET: libc::c_long = 4000 + 447;
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "mips64", target_arch = "mips64r6"),
+    target_pointer_width = "64"
+))]
+const SYS_MEMFD_SECRET: libc::c_long = 5000 + 447;
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "mips64", target_arch = "mips64r6"),
+    target_pointer_width = "32"
+))]
+const SYS_MEMFD_SECRET: libc::c_long = 6000 + 447;
+#[cfg(all(
+    target_os = "linux",
+    target_arch = "x86_64",
+    target_pointer_width = "32"
+))]
+const SYS_MEMFD_SECRET: libc::c_long = 0x4000_0000 + 447;
+#[cfg(all(
+    target_os = "linux",
+    not(any(
+        target_arch = "mips",
+        target_arch = "mips32r6",
+        target_arch = "mips64",
+        target_arch = "mips64r6",
+        all(target_arch = "x86_64", target_pointer_width = "32")
+    ))
+))]
+const SYS_MEMFD_SECRET: libc::c_long = 447;
+
+/// An anonymous or `memfd_secret` memory mapping, unmapped on drop.
+pub(crate) struct Region {
+    ptr: NonNull<u8>,
+    len: usize,
+    shared: bool,
+    residency: Residency,
+    // The pages which hold the secrets, and are locked and counted in
+    // the budget; the whole region unless it has guard pages.
+    lock_offset: usize,
+    lock_len: usize,
+    fork_slot: Option<&'static ForkSlot>,
+}
+
+impl Region {
+    /// Maps `len` bytes, rounded up to whole pages, of zeroed memory.
+    pub(crate) fn map(len: usize) -> Result<Self, OsError> {
+        let len = round_to_pages(len);
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(OsError::last("mmap"));
+        }
+        Ok(Region {
+            ptr: NonNull::new(ptr as *mut u8).unwrap(),
+            len,
+            shared: false,
+            residency: Residency::Other,
+            lock_offset: 0,
+            lock_len: len,
+            fork_slot: None,
+        })
+    }
+
+    /// Maps `len` bytes, rounded up to whole pages, of zeroed memory
+    /// from `memfd_secret`, which is locked into RAM and removed from
+    /// the kernel direct map.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn map_secret(len: usize) -> Result<Self, OsError> {
+        let len = round_to_pages(len);
+        let fd = unsafe { libc::syscall(SYS_MEMFD_SECRET, libc::O_CLOEXEC) } as libc::c_int;
+        if fd < 0 {
+            return Err(OsError::last("memfd_secret"));
+        }
+        let result = if unsafe { libc::ftruncate(fd, len as libc::off_t) } != 0 {
+            Err(OsError::last("ftruncate"))
+        } else {
+            let ptr = unsafe {
+                libc::mmap(
+                    ptr::null_mut(),
+                    len,
+                    libc::PROT_READ | libc::PROT_WRITE,
+                    libc::MAP_SHARED,
+                    fd,
+                    0,
+                )
+            };
+            if ptr == libc::MAP_FAILED {
+                Err(OsError::last("mmap"))
+            } else {
+                budget::add(Residency::Locked, len);
+                Ok(Region {
+                    ptr: NonNull::new(ptr as *mut u8).unwrap(),
+                    len,
+                    shared: true,
+                    residency: Residency::Locked,
+                    lock_offset: 0,
+                    lock_len: len,
+                    fork_slot: None,
+                })
+            }
+        };
+        unsafe {
+            libc::close(fd);
+        }
+        result
+    }
+
+    #[inline]
+    pub(crate) fn as_ptr(&self) -> *mut u8 {
+        self.ptr.as_ptr()
+    }
+
+    #[inline]
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Restricts `lock` and the budget to `len` bytes at `offset`, which
+    /// must both be multiples of the page size, leaving out guard pages.
+    pub(crate) fn set_lock_range(&mut self, offset: usize, len: usize) {
+        debug_assert!(self.residency == Residency::Other);
+        debug_assert!(offset & (page_size() - 1) == 0 && offset + len <= self.len);
+        self.lock_offset = offset;
+        self.lock_len = len;
+    }
+
+    #[inline]
+    fn lock_ptr(&self) -> *mut u8 {
+        unsafe { self.as_ptr().add(self.lock_offset) }
+    }
+
+    /// Locks the region into RAM, so it is never written to swap, until
+    /// it is unmapped.
+    pub(crate) fn lock(&mut self) -> Result<(), OsError> {
+        debug_assert!(self.residency == Residency::Other);
+        if unsafe { libc::mlock(self.lock_ptr() as *const _, self.lock_len) } != 0 {
+            budget::add_failure();
+            return Err(OsError::last("mlock"));
+        }
+        self.set_residency(Residency::Locked);
+        Ok(())
+    }
+
+    /// Records that the region holds secrets, but could not be locked.
+    pub(crate) fn keep_unlocked(&mut self) {
+        debug_assert!(self.residency == Residency::Other);
+        self.set_residency(Residency::Unlocked);
+    }
+
+    fn set_residency(&mut self, residency: Residency) {
+        budget::add(residency, self.lock_len);
+        self.residency = residency;
+    }
+
+    /// Changes the access protection of `len` bytes at `offset`, which
+    /// must both be multiples of the page size.
+    pub(crate) fn protect(
+        &self,
+        offset: usize,
+        len: usize,
+        prot: libc::c_int,
+    ) -> Result<(), OsError> {
+        debug_assert!(offset & (page_size() - 1) == 0 && offset + len <= self.len);
+        let ptr = unsafe { self.as_ptr().add(offset) };
+        if unsafe { libc::mprotect(ptr as *mut _, len, prot) } != 0 {
+            return Err(OsError::last("mprotect"));
+        }
+        Ok(())
+    }
+
+    /// Excludes the region from core dumps.
+    pub(crate) fn dont_dump(&mut self) -> Result<(), OsError> {
+        madvise(self.as_ptr(), self.len, libc::MADV_DONTDUMP)
+    }
+
+    /// Makes forked children see the region as zeroed memory.
+    ///
+    /// Uses `MADV_WIPEONFORK` if possible, or else replaces the region
+    /// with new memory in the child from a `pthread_atfork` handler.
+    pub(crate) fn wipe_on_fork(&mut self) -> Result<(), OsError> {
+        if self.fork_slot.is_some() {
+            return Ok(());
+        }
+        if !self.shared {
+            match madvise(self.as_ptr(), self.len, libc::MADV_WIPEONFORK) {
+                Err(ref err) if err.errno() == libc::EINVAL => {}
+                result => return result,
+            }
+        }
+        self.fork_slot = Some(register_for_fork(self.as_ptr(), self.len)?);
+        Ok(())
+    }
+
+    /// Overwrites the whole region with zeros.
+    #[inline]
+    pub(crate) fn wipe(&mut self) {
+        unsafe { slice::from_raw_parts_mut(self.as_ptr(), self.len) }.clear();
+    }
+}
+
+impl Drop for Region {
+    fn drop(&mut self) {
+        if let Some(slot) = self.fork_slot {
+            unregister_for_fork(slot);
+        }
+        if self.residency == Residency::Locked && !self.shared {
+            unsafe {
+                libc::munlock(self.lock_ptr() as *const _, self.lock_len);
+            }
+        }
+        budget::sub(self.residency, self.lock_len);
+        unsafe {
+            libc::munmap(self.as_ptr() as *mut _, self.len);
+        }
+    }
+}
+
+// The region is plain memory, owned like a Box.
+unsafe impl Send for Region {}
+unsafe impl Sync for Region {}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::{register_for_fork, stack_bounds, unregister_for_fork, Region, FORK_SLOTS};
+
+    use core::slice;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::thread;
+
+    use libc;
+
+    // Runs `f` in a forked child, and returns whether it returned true.
+    pub(crate) fn in_child<F: FnOnce() -> bool>(f: F) -> bool {
+        let pid = unsafe { libc::fork() };
+        assert!(pid >= 0);
+        if pid == 0 {
+            // A panic must not unwind into the test harness in the child.
+            let code = match panic::catch_unwind(AssertUnwindSafe(f)) {
+                Ok(true) => 0,
+                _ => 1,
+            };
+            unsafe { libc::_exit(code) };
+        }
+        let mut status = 0;
+        assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+        libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0
+    }
+
+    #[test]
+    fn fork_registry() {
+        let mut region = Region::map(1).unwrap();
+        let data = unsafe { slice::from_raw_parts_mut(region.as_ptr(), region.len()) };
+        for byte in data.iter_mut() {
+            *byte = 0x41;
+        }
+
+        assert!(in_child(|| data.iter().all(|&b| b == 0x41)));
+        let slot = register_for_fork(region.as_ptr(), region.len()).unwrap();
+        assert!(in_child(|| data.iter().all(|&b| b == 0)));
+        unregister_for_fork(slot);
+        assert!(in_child(|| data.iter().all(|&b| b == 0x41)));
+
+        region.wipe_on_fork().unwrap();
+        assert!(in_child(|| data.iter().all(|&b| b == 0)));
+        assert!(data.iter().all(|&b| b == 0x41));
+    }
+
+    #[test]
+    fn fork_registry_grows() {
+        let other = Region::map(1).unwrap();
+        let taken: Vec<_> = (0..FORK_SLOTS)
+            .map(|_| register_for_fork(other.as_ptr(), other.len()).unwrap())
+            .collect();
+
+        let region = Region::map(1).unwrap();
+        let data = unsafe { slice::from_raw_parts_mut(region.as_ptr(), region.len()) };
+        for byte in data.iter_mut() {
+            *byte = 0x41;
+        }
+        let slot = register_for_fork(region.as_ptr(), region.len()).unwrap();
+        assert!(in_child(|| data.iter().all(|&b| b == 0)));
+
+        unregister_for_fork(slot);
+        for slot in taken {
+            unregister_for_fork(slot);
+        }
+        assert!(in_child(|| data.iter().all(|&b| b == 0x41)));
+    }
+
+    fn on_stack() -> bool {
+        let local = 0u8;
+        let here = &local as *const u8 as usize;
+        match (stack_bounds(), stack_bounds()) {
+            (Some((low, high)), Some(again)) => low < here && here < high && again == (low, high),
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn thread_stack_bounds() {
+        assert!(on_stack());
+        // In the child, the calling thread is the main thread.
+        assert!(in_child(on_stack));
+        // Each thread has its own bounds.
+        let main = stack_bounds();
+        let other = thread::spawn(|| (on_stack(), stack_bounds()))
+            .join()
+            .unwrap();
+        assert!(other.0);
+        assert_ne!(other.1, main);
+    }
+}